@@ -0,0 +1,530 @@
+use anyhow::{Result, anyhow};
+use serde_json::Value;
+use std::env;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+
+use crate::cancel::CancelToken;
+use crate::compaction::{self, Compaction, CompactionConfig};
+use crate::error::Error;
+use crate::hooks::{HookCall, Hooks};
+use crate::ignore_rules::IgnoreRules;
+use crate::llm::{Backend, GenerationParams, send_to_llm};
+use crate::memory::load_memory;
+use crate::message::{ChatMessage, ToolCall};
+use crate::prompt::{render, template_variables};
+use crate::sandbox::sanitize_and_resolve_path;
+use crate::session::{AnsweredBy, Cancellation, Session};
+use crate::tokens::{BudgetConfig, TokenBudget, estimate_message, estimate_tools};
+use crate::tools::remember::Remember;
+use crate::tools::repo_map::RepoMap;
+use crate::tools::{ToolDefinition, ToolRegistry};
+
+pub const DEFAULT_SYSTEM_PROMPT: &str = include_str!("system_prompt.md");
+
+/// Argument names that hold paths and are confined to the workspace.
+const PATH_ARGUMENTS: &[&str] = &["path", "from_path", "to_path"];
+
+/// Progress reported while an agent works through a prompt.
+pub enum AgentEvent<'a> {
+    AssistantMessage(&'a str),
+    ToolCall {
+        name: &'a str,
+        args: &'a Value,
+    },
+    ToolOutput {
+        name: &'a str,
+        output: &'a str,
+    },
+    ToolError {
+        name: &'a str,
+        error: &'a anyhow::Error,
+    },
+    /// Something the user should know that does not stop the run.
+    Notice(&'a str),
+}
+
+pub type EventHandler = Box<dyn Fn(&AgentEvent) + Send + Sync>;
+
+/// Asked before running a tool that requires approval, with the tool's
+/// name and arguments. Returns whether the call may go ahead.
+pub type ApprovalHandler = Box<dyn Fn(&str, &Value) -> bool + Send + Sync>;
+
+/// Sets up a fallback's backend and the model to ask there.
+pub type FallbackLoader = Box<dyn Fn() -> Result<(Backend, String)> + Send + Sync>;
+
+/// A backend to try when the ones before it are unavailable, and the
+/// model to ask there. It is only set up the first time it is needed, so
+/// that a broken fallback does not stop runs that never use it.
+pub struct Fallback {
+    /// Names the fallback in notices before it is loaded.
+    pub name: String,
+    load: FallbackLoader,
+    loaded: OnceLock<Result<(Backend, String), String>>,
+}
+
+impl Fallback {
+    /// A fallback that is ready to use.
+    pub fn new(model: impl Into<String>, backend: Backend) -> Self {
+        let ready = (backend, model.into());
+        Fallback {
+            name: ready.0.name.clone(),
+            load: Box::new(move || Ok(ready.clone())),
+            loaded: OnceLock::new(),
+        }
+    }
+
+    /// A fallback set up by `load` when first needed.
+    pub fn lazy(name: impl Into<String>, load: FallbackLoader) -> Self {
+        Fallback {
+            name: name.into(),
+            load,
+            loaded: OnceLock::new(),
+        }
+    }
+
+    /// The backend and model, loading them on first use. A failure to
+    /// load is remembered, so it is only reported once.
+    fn get(&self) -> Result<&(Backend, String), &str> {
+        self.loaded
+            .get_or_init(|| (self.load)().map_err(|e| format!("{:#}", e)))
+            .as_ref()
+            .map_err(String::as_str)
+    }
+}
+
+/// Sends prompts to a model and carries out the tool calls it asks for.
+pub struct Agent {
+    pub model: String,
+    pub backend: Backend,
+    /// Tried in order when a request to `backend` fails with a transport
+    /// error or a retryable status even after retries. Any other error,
+    /// and any answer from the model, is final.
+    pub fallbacks: Vec<Fallback>,
+    pub generation: GenerationParams,
+    /// System prompt template, rendered with [`template_variables`] when a
+    /// session starts.
+    pub system_prompt: String,
+    pub tools: ToolRegistry,
+    pub hooks: Hooks,
+    /// Root directory that every tool path is resolved against.
+    pub workspace: PathBuf,
+    /// Ignore rules for the workspace; reload them when changing `workspace`.
+    pub ignore: Arc<IgnoreRules>,
+    /// Context window budget checked before every request. `None` sends
+    /// requests unchecked.
+    pub budget: Option<TokenBudget>,
+    /// When set, older turns are summarized once the session nears the
+    /// context window. Requires a known `budget`.
+    pub compaction: Option<CompactionConfig>,
+    /// Upper bound on model round trips for a single prompt.
+    pub max_turns: usize,
+    pub on_event: Option<EventHandler>,
+    /// Decides on calls to tools that require approval. `None` refuses
+    /// them all.
+    pub approve: Option<ApprovalHandler>,
+    /// Stops the run when cancelled: a request to the model is abandoned,
+    /// read-only tools are stopped, and a tool that changes files is left
+    /// to finish so that no file is half written. The run then ends with
+    /// an [`Error::Cancelled`]. Each run resets the token when it starts,
+    /// so a cancel only applies to the run in progress. `None` runs cannot
+    /// be cancelled.
+    pub cancel: Option<Arc<CancelToken>>,
+    /// Ends the run at the first failed tool call instead of reporting
+    /// the failure to the model, for scripts that need to know.
+    pub stop_on_tool_error: bool,
+}
+
+impl Agent {
+    /// An agent with the default system prompt and all built-in tools,
+    /// working in the current directory.
+    pub fn new(
+        model: impl Into<String>,
+        endpoint: impl Into<String>,
+        api_key: impl Into<String>,
+    ) -> Self {
+        let workspace = env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+        let ignore = IgnoreRules::load(&workspace).unwrap_or_else(|_| IgnoreRules::none());
+        let model = model.into();
+        Agent {
+            budget: BudgetConfig::default().for_model(&model),
+            compaction: Some(CompactionConfig::default()),
+            model,
+            backend: Backend::new(endpoint, api_key),
+            fallbacks: Vec::new(),
+            generation: GenerationParams::default(),
+            system_prompt: DEFAULT_SYSTEM_PROMPT.to_string(),
+            tools: ToolRegistry::builtin(),
+            hooks: Hooks::default(),
+            workspace,
+            ignore: Arc::new(ignore),
+            max_turns: 25,
+            on_event: None,
+            approve: None,
+            cancel: None,
+            stop_on_tool_error: false,
+        }
+    }
+
+    /// Moves the agent to another workspace and loads its ignore rules.
+    /// The `repo_map` tool, if registered, shares those rules, and the
+    /// `remember` tool saves project memory there.
+    pub fn set_workspace(&mut self, workspace: PathBuf) -> Result<()> {
+        self.ignore = Arc::new(IgnoreRules::load(&workspace)?);
+        if self.tools.get("repo_map").is_some() {
+            self.tools
+                .register(Box::new(RepoMap::new(self.ignore.clone())));
+        }
+        if self.tools.get("remember").is_some() {
+            self.tools
+                .register(Box::new(Remember::new(workspace.clone())));
+        }
+        self.workspace = workspace;
+        Ok(())
+    }
+
+    /// Starts a session with the rendered system prompt, followed by the
+    /// user and project memory files.
+    pub fn new_session(&self) -> Session {
+        let vars = template_variables(&self.workspace, &self.tools, &self.ignore);
+        let mut system_prompt = render(&self.system_prompt, &vars);
+        let memory = load_memory(&self.workspace);
+        if !memory.is_empty() {
+            system_prompt.push_str("\n\n");
+            system_prompt.push_str(&memory);
+        }
+        Session::new(system_prompt, self.model.clone(), self.tools.definitions())
+    }
+
+    /// Sends `prompt` and keeps executing tool calls, feeding their results
+    /// back, until the model answers without requesting any more tools.
+    pub async fn run(
+        &self,
+        session: &mut Session,
+        prompt: impl Into<String>,
+    ) -> Result<ChatMessage> {
+        if let Some(cancel) = &self.cancel {
+            cancel.reset();
+        }
+        session.push(ChatMessage::user(prompt))?;
+
+        let follow_up = self.generation.for_follow_up();
+        for turn in 0..self.max_turns {
+            let tools = self.tools.definitions();
+            let params = if turn == 0 {
+                &self.generation
+            } else {
+                &follow_up
+            };
+            let response = self
+                .until_cancelled(async {
+                    let messages = self.prepare_request(session, &tools).await?;
+                    self.send(&messages, &tools, &self.model, params).await
+                })
+                .await;
+            let Some(response) = response else {
+                return Err(self.record_cancellation(session, "waiting for the model"));
+            };
+            let (response, backend, model) = response?;
+            session.answered_by.push(AnsweredBy {
+                message: session.messages.len(),
+                backend: backend.to_string(),
+                model: model.to_string(),
+            });
+            session.push(response.clone())?;
+
+            if let Some(content) = response.text_content() {
+                self.emit(AgentEvent::AssistantMessage(&content));
+            }
+
+            let tool_calls = match &response.tool_calls {
+                Some(tool_calls) if !tool_calls.is_empty() => tool_calls,
+                _ => return Ok(response),
+            };
+
+            for (i, tool_call) in tool_calls.iter().enumerate() {
+                let name = &tool_call.function.name;
+                let read_only = self.tools.get(name).is_some_and(|tool| tool.read_only());
+                let result = if self.is_cancelled() {
+                    None
+                } else if read_only {
+                    self.until_cancelled(self.execute_tool_call(tool_call))
+                        .await
+                } else {
+                    Some(self.execute_tool_call(tool_call).await)
+                };
+                let Some(result) = result else {
+                    // Every tool call needs a result for the transcript to
+                    // remain a valid conversation.
+                    for skipped in &tool_calls[i..] {
+                        session.push(ChatMessage::tool_result(
+                            &skipped.id,
+                            "Cancelled by the user",
+                        ))?;
+                    }
+                    return Err(self.record_cancellation(session, &format!("running {}", name)));
+                };
+                let output = match result {
+                    Ok(output) => output,
+                    Err(e) => {
+                        self.emit(AgentEvent::ToolError { name, error: &e });
+                        session.push(ChatMessage::tool_result(
+                            &tool_call.id,
+                            format!("Error: {}", e),
+                        ))?;
+                        if self.stop_on_tool_error {
+                            return Err(tool_failure(name, e));
+                        }
+                        continue;
+                    }
+                };
+                session.push(ChatMessage::tool_result(&tool_call.id, output))?;
+            }
+        }
+
+        Err(anyhow!(
+            "Stopped after {} turns without a final answer",
+            self.max_turns
+        ))
+    }
+
+    /// Builds the messages for the next request, compacting older turns
+    /// when the session nears the context window and checking the result
+    /// against the budget.
+    async fn prepare_request(
+        &self,
+        session: &mut Session,
+        tools: &[ToolDefinition],
+    ) -> Result<Vec<ChatMessage>> {
+        let Some(budget) = &self.budget else {
+            return Ok(session.context_messages());
+        };
+
+        if let Some(compaction) = &self.compaction {
+            let used = estimate_tools(tools)
+                + session
+                    .context_messages()
+                    .iter()
+                    .map(estimate_message)
+                    .sum::<usize>();
+            if used as f64 > compaction.threshold * budget.limit() as f64 {
+                self.compact(session, compaction).await?;
+            }
+        }
+
+        let context = session.context_messages();
+        let messages = budget.fit(&context, tools)?;
+        let dropped = context.len() - messages.len();
+        if dropped > 0 {
+            self.emit(AgentEvent::Notice(&format!(
+                "Left {} older message(s) out of the request to fit the {}-token context window",
+                dropped, budget.context_window
+            )));
+        }
+        Ok(messages)
+    }
+
+    /// Replaces older turns with a model-written summary. The full
+    /// transcript stays in `session.messages`.
+    pub async fn compact(&self, session: &mut Session, config: &CompactionConfig) -> Result<()> {
+        let previous = session.compactions.last();
+        let Some(through) = compaction::plan(&session.messages, previous, config.keep_recent_turns)
+        else {
+            return Ok(());
+        };
+        let from = previous.map_or(1, |c| c.through);
+        let request = compaction::summary_request(previous, &session.messages[from..through]);
+        let model = config.model.as_deref().unwrap_or(&self.model);
+        let (response, ..) = self.send(&request, &[], model, &self.generation).await?;
+        let summary = response
+            .text_content()
+            .map(|s| s.into_owned())
+            .filter(|s| !s.trim().is_empty())
+            .ok_or_else(|| anyhow!("The model returned an empty conversation summary"))?;
+
+        session.compactions.push(Compaction { through, summary });
+        session.save()?;
+        self.emit(AgentEvent::Notice(&format!(
+            "Compacted {} earlier message(s) into a summary",
+            through - from
+        )));
+        Ok(())
+    }
+
+    pub async fn execute_tool_call(&self, tool_call: &ToolCall) -> Result<String> {
+        let name = tool_call.function.name.as_str();
+        let mut args: Value = serde_json::from_str(&tool_call.function.arguments)?;
+
+        self.emit(AgentEvent::ToolCall { name, args: &args });
+
+        let mut paths = Vec::new();
+        if let Some(obj) = args.as_object_mut() {
+            for key in PATH_ARGUMENTS {
+                if let Some(path_str) = obj.get(*key).and_then(|v| v.as_str()) {
+                    let safe_path = sanitize_and_resolve_path(&self.workspace, path_str)?;
+                    obj.insert(
+                        key.to_string(),
+                        Value::String(safe_path.to_string_lossy().to_string()),
+                    );
+                    paths.push(safe_path);
+                }
+            }
+        }
+
+        let workspace = self.workspace.canonicalize()?;
+        let call = HookCall {
+            tool: name,
+            arguments: &args,
+            paths: &paths,
+            workspace: &workspace,
+        };
+        let tool = self
+            .tools
+            .get(name)
+            .ok_or_else(|| anyhow!("Unknown tool function: {}", name))?;
+        let ignored: Vec<&PathBuf> = paths
+            .iter()
+            .filter(|path| self.ignore.is_ignored(path, path.is_dir()))
+            .collect();
+        if tool.read_only()
+            && let Some(path) = ignored.first()
+        {
+            return Err(Error::Sandbox(format!(
+                "'{}' is excluded by .gitignore or .juniorignore",
+                path.strip_prefix(&workspace).unwrap_or(path).display()
+            ))
+            .into());
+        }
+
+        self.hooks.before(&call).await?;
+        if tool.requires_approval()
+            && !self
+                .approve
+                .as_ref()
+                .is_some_and(|approve| approve(name, &args))
+        {
+            return Err(anyhow!("The user did not approve this {} call", name));
+        }
+        // The approval prompt may have kept a cancel from taking effect.
+        if self.is_cancelled() {
+            return Err(Error::Cancelled.into());
+        }
+        let mut output = tool.call(args.clone()).await?;
+        for path in ignored {
+            output.push_str(&format!(
+                "\nWarning: '{}' is excluded by .gitignore or .juniorignore",
+                path.strip_prefix(&workspace).unwrap_or(path).display()
+            ));
+        }
+        let output = self.hooks.after(&call, output).await?;
+        self.emit(AgentEvent::ToolOutput {
+            name,
+            output: &output,
+        });
+
+        Ok(output)
+    }
+
+    /// Sends a request to `model` on the primary backend, then to each
+    /// fallback in turn while the backends are unavailable. Returns the
+    /// response with the name of the backend and the model that gave it.
+    async fn send<'a>(
+        &'a self,
+        messages: &[ChatMessage],
+        tools: &[ToolDefinition],
+        model: &'a str,
+        params: &GenerationParams,
+    ) -> Result<(ChatMessage, &'a str, &'a str)> {
+        let mut current = (&self.backend, model);
+        let mut result = self.send_to(current, messages, tools, params).await;
+        for fallback in &self.fallbacks {
+            match &result {
+                Err(e) if Error::find(e).is_some_and(Error::is_retryable) => {}
+                _ => break,
+            }
+            let (backend, model) = match fallback.get() {
+                Ok(loaded) => loaded,
+                Err(e) => {
+                    self.emit(AgentEvent::Notice(&format!(
+                        "Skipping fallback {}: {}",
+                        fallback.name, e
+                    )));
+                    continue;
+                }
+            };
+            if let Err(e) = &result {
+                self.emit(AgentEvent::Notice(&format!(
+                    "{} is unavailable ({}); falling back to {} ({})",
+                    current.0.name, e, backend.name, model
+                )));
+            }
+            current = (backend, model);
+            result = self.send_to(current, messages, tools, params).await;
+        }
+        result.map(|response| (response, current.0.name.as_str(), current.1))
+    }
+
+    async fn send_to(
+        &self,
+        (backend, model): (&Backend, &str),
+        messages: &[ChatMessage],
+        tools: &[ToolDefinition],
+        params: &GenerationParams,
+    ) -> Result<ChatMessage> {
+        send_to_llm(backend, messages, model, tools, params, &|notice| {
+            self.emit(AgentEvent::Notice(notice))
+        })
+        .await
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .is_some_and(|cancel| cancel.is_cancelled())
+    }
+
+    /// Runs `work` unless the run is cancelled first.
+    async fn until_cancelled<T>(&self, work: impl Future<Output = T>) -> Option<T> {
+        let Some(cancel) = &self.cancel else {
+            return Some(work.await);
+        };
+        tokio::select! {
+            biased;
+            _ = cancel.cancelled() => None,
+            output = work => Some(output),
+        }
+    }
+
+    /// Marks the cancellation in the transcript and saves it, returning
+    /// the error the run ends with.
+    fn record_cancellation(&self, session: &mut Session, during: &str) -> anyhow::Error {
+        session.cancellations.push(Cancellation {
+            after: session.messages.len(),
+            during: during.to_string(),
+        });
+        match session.save() {
+            Ok(()) => Error::Cancelled.into(),
+            Err(e) => e,
+        }
+    }
+
+    fn emit(&self, event: AgentEvent) {
+        if let Some(handler) = &self.on_event {
+            handler(&event);
+        }
+    }
+}
+
+/// Classifies a failed tool call: sandbox violations and cancellations
+/// keep their class, anything else is a tool failure.
+fn tool_failure(tool: &str, error: anyhow::Error) -> anyhow::Error {
+    if Error::find(&error).is_some() {
+        return error;
+    }
+    Error::Tool {
+        tool: tool.to_string(),
+        message: format!("{:#}", error),
+    }
+    .into()
+}
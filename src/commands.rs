@@ -0,0 +1,274 @@
+use anyhow::{Context, Result, anyhow};
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::prompt::{JUNIOR_DIR, PLACEHOLDER_PATTERN, render, user_junior_dir};
+
+const COMMANDS_DIR: &str = "commands";
+
+/// A reusable prompt stored as `<name>.md` in `.junior/commands/` or
+/// `~/.junior/commands/`, run as `junior /<name> [args...]`.
+///
+/// The file may start with a front matter block that pins settings for the
+/// run:
+///
+/// ```text
+/// ---
+/// description: Review a file for error handling gaps
+/// model: gpt-4o
+/// tools: readonly
+/// ---
+/// Review {{1}} for error handling gaps.
+/// ```
+///
+/// The body can use `{{1}}`, `{{2}}`, ... for positional arguments,
+/// `{{args}}` for all of them, `{{files}}` for the attached file paths and
+/// any name passed with `--var name=value`.
+#[derive(Debug, Clone)]
+pub struct PromptCommand {
+    pub name: String,
+    pub path: PathBuf,
+    pub description: Option<String>,
+    pub model: Option<String>,
+    pub tool_set: Option<String>,
+    pub template: String,
+}
+
+impl PromptCommand {
+    pub fn load(name: &str, path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read command {}", path.display()))?;
+        let mut command = PromptCommand {
+            name: name.to_string(),
+            path: path.to_path_buf(),
+            description: None,
+            model: None,
+            tool_set: None,
+            template: text.clone(),
+        };
+
+        // `lines()` also splits on CRLF, for files saved on Windows.
+        let lines: Vec<&str> = text.lines().collect();
+        if lines.first() != Some(&"---") {
+            return Ok(command);
+        }
+        let end = 1 + lines[1..]
+            .iter()
+            .position(|line| *line == "---")
+            .ok_or_else(|| anyhow!("Unterminated front matter in {}", path.display()))?;
+        let front_matter = &lines[1..end];
+        let body = lines[end + 1..].join("\n");
+
+        for line in front_matter.iter().filter(|l| !l.trim().is_empty()) {
+            let (key, value) = line.split_once(':').ok_or_else(|| {
+                anyhow!("Invalid front matter line in {}: {}", path.display(), line)
+            })?;
+            let value = value.trim().trim_matches('"').to_string();
+            match key.trim() {
+                "description" => command.description = Some(value),
+                "model" => command.model = Some(value),
+                "tools" => command.tool_set = Some(value),
+                other => {
+                    return Err(anyhow!(
+                        "Unknown front matter key '{}' in {}",
+                        other,
+                        path.display()
+                    ));
+                }
+            }
+        }
+        command.template = body;
+
+        Ok(command)
+    }
+
+    /// Fills in the template, failing if any placeholder is left without a
+    /// value.
+    pub fn render(
+        &self,
+        args: &[String],
+        files: &[String],
+        vars: &HashMap<String, String>,
+    ) -> Result<String> {
+        let mut values = vars.clone();
+        for (i, arg) in args.iter().enumerate() {
+            values.insert((i + 1).to_string(), arg.clone());
+        }
+        values.insert("args".to_string(), args.join(" "));
+        values.insert("files".to_string(), files.join(", "));
+
+        // Check the template rather than the result, which may contain
+        // braces that came in with an argument.
+        let placeholder = Regex::new(PLACEHOLDER_PATTERN).unwrap();
+        let mut missing: Vec<&str> = Vec::new();
+        for caps in placeholder.captures_iter(&self.template) {
+            let name = caps.get(1).unwrap().as_str();
+            if !values.contains_key(name) && !missing.contains(&name) {
+                missing.push(name);
+            }
+        }
+        if !missing.is_empty() {
+            return Err(anyhow!(
+                "Command /{} is missing values for: {}",
+                self.name,
+                missing.join(", ")
+            ));
+        }
+
+        Ok(render(&self.template, &values).trim().to_string())
+    }
+}
+
+fn command_dirs(workspace: &Path) -> Vec<PathBuf> {
+    let mut dirs = vec![workspace.join(JUNIOR_DIR).join(COMMANDS_DIR)];
+    if let Some(dir) = user_junior_dir() {
+        dirs.push(dir.join(COMMANDS_DIR));
+    }
+    dirs
+}
+
+/// Finds a command by name, preferring the project's definition over the
+/// user's.
+pub fn find_command(workspace: &Path, name: &str) -> Result<PromptCommand> {
+    for dir in command_dirs(workspace) {
+        let path = dir.join(format!("{}.md", name));
+        if path.is_file() {
+            return PromptCommand::load(name, &path);
+        }
+    }
+
+    let available = available_commands(workspace);
+    if available.is_empty() {
+        Err(anyhow!(
+            "Unknown command /{}: no commands found in .junior/commands/ or ~/.junior/commands/",
+            name
+        ))
+    } else {
+        Err(anyhow!(
+            "Unknown command /{} (available: /{})",
+            name,
+            available.join(", /")
+        ))
+    }
+}
+
+pub fn available_commands(workspace: &Path) -> Vec<String> {
+    let mut names: Vec<String> = command_dirs(workspace)
+        .iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "md"))
+        .filter_map(|path| Some(path.file_stem()?.to_string_lossy().to_string()))
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(text: &str) -> Result<PromptCommand> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("review.md");
+        fs::write(&path, text).unwrap();
+        PromptCommand::load("review", &path)
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn loads_front_matter_with_either_line_ending() {
+        for text in [
+            "---\ndescription: Review a file\nmodel: \"gpt-4o\"\ntools: readonly\n---\nReview {{1}}.\n",
+            "---\r\ndescription: Review a file\r\nmodel: \"gpt-4o\"\r\ntools: readonly\r\n---\r\nReview {{1}}.\r\n",
+        ] {
+            let command = load(text).unwrap();
+
+            assert_eq!(command.description.as_deref(), Some("Review a file"));
+            assert_eq!(command.model.as_deref(), Some("gpt-4o"));
+            assert_eq!(command.tool_set.as_deref(), Some("readonly"));
+            assert_eq!(command.template, "Review {{1}}.");
+        }
+    }
+
+    #[test]
+    fn a_file_without_front_matter_is_all_template() {
+        let command = load("Explain {{args}}\n---\nsomething else\n").unwrap();
+
+        assert_eq!(command.description, None);
+        assert_eq!(command.template, "Explain {{args}}\n---\nsomething else\n");
+    }
+
+    #[test]
+    fn rejects_malformed_front_matter() {
+        let error = load("---\nmodel: gpt-4o\nReview {{1}}.\n").unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("Unterminated front matter in ")
+        );
+
+        let error = load("---\ntemperature: 0\n---\nhi\n").unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("Unknown front matter key 'temperature' in ")
+        );
+
+        let error = load("---\njust words\n---\nhi\n").unwrap_err();
+        assert!(error.to_string().ends_with(": just words"), "{}", error);
+    }
+
+    #[test]
+    fn renders_arguments_files_and_variables() {
+        let command =
+            load("Compare {{1}} with {{ 2 }} in {{files}} ({{args}}) for {{team}}.\n").unwrap();
+        let vars = HashMap::from([("team".to_string(), "infra".to_string())]);
+
+        let prompt = command
+            .render(
+                &strings(&["old", "new"]),
+                &strings(&["a.rs", "b.rs"]),
+                &vars,
+            )
+            .unwrap();
+
+        assert_eq!(
+            prompt,
+            "Compare old with new in a.rs, b.rs (old new) for infra."
+        );
+    }
+
+    #[test]
+    fn reports_each_missing_value_once() {
+        let command = load("Compare {{1}} with {{2}} for {{team}}, then {{2}} again.").unwrap();
+
+        let error = command
+            .render(&strings(&["old"]), &[], &HashMap::new())
+            .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Command /review is missing values for: 2, team"
+        );
+    }
+
+    #[test]
+    fn arguments_may_contain_braces() {
+        let command = load("Explain {{1}}").unwrap();
+
+        let prompt = command
+            .render(&strings(&["the {{name}} syntax"]), &[], &HashMap::new())
+            .unwrap();
+
+        assert_eq!(prompt, "Explain the {{name}} syntax");
+    }
+}
@@ -0,0 +1,200 @@
+use serde::{Deserialize, Serialize};
+
+use crate::message::{ChatMessage, Role};
+
+/// Longest excerpt of a single message included in a summary request, so
+/// that summarizing a huge tool output cannot overflow the window itself.
+const MAX_EXCERPT_CHARS: usize = 4_000;
+
+const SUMMARIZER_PROMPT: &str = "You compress conversations between a user and a coding assistant. \
+Write a concise summary of the conversation so far that lets the assistant continue the work: \
+the user's goals, decisions made, files read or changed and what was learned from them, \
+and anything still unresolved. Do not add commentary.";
+
+/// Settings from the `[compaction]` config table.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CompactionConfig {
+    pub enabled: bool,
+    /// Fraction of the context window in use that triggers compaction.
+    pub threshold: f64,
+    /// Most recent turns that are always sent verbatim. A turn starts at
+    /// each user message and each model reply, so a reply's tool results
+    /// stay with it.
+    pub keep_recent_turns: usize,
+    /// Model used to write summaries. Defaults to the session's model.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+}
+
+impl Default for CompactionConfig {
+    fn default() -> Self {
+        CompactionConfig {
+            enabled: true,
+            threshold: 0.8,
+            keep_recent_turns: 4,
+            model: None,
+        }
+    }
+}
+
+/// A summary standing in for the transcript's messages before `through`,
+/// apart from the system prompt.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Compaction {
+    pub through: usize,
+    pub summary: String,
+}
+
+impl Compaction {
+    pub fn message(&self) -> ChatMessage {
+        ChatMessage::user(format!(
+            "Summary of the conversation so far:\n\n{}",
+            self.summary
+        ))
+    }
+}
+
+/// Where the next compaction should end: the start of the oldest turn that
+/// must stay verbatim. `None` when there is nothing new to compact.
+pub fn plan(
+    messages: &[ChatMessage],
+    previous: Option<&Compaction>,
+    keep_recent_turns: usize,
+) -> Option<usize> {
+    let turn_starts: Vec<usize> = messages
+        .iter()
+        .enumerate()
+        .skip(1)
+        .filter(|(_, m)| matches!(m.role, Role::User | Role::Assistant))
+        .map(|(i, _)| i)
+        .collect();
+    let keep = keep_recent_turns.max(1);
+    let cut = *turn_starts.get(turn_starts.len().checked_sub(keep)?)?;
+    let compacted_so_far = previous.map_or(1, |c| c.through);
+    (cut > compacted_so_far).then_some(cut)
+}
+
+/// The request that asks a model to fold `messages` into the previous
+/// summary.
+pub fn summary_request(
+    previous: Option<&Compaction>,
+    messages: &[ChatMessage],
+) -> Vec<ChatMessage> {
+    let mut transcript = String::new();
+    if let Some(previous) = previous {
+        transcript.push_str(&format!("[earlier summary]\n{}\n\n", previous.summary));
+    }
+    for message in messages {
+        transcript.push_str(&describe(message));
+        transcript.push_str("\n\n");
+    }
+    vec![
+        ChatMessage::system(SUMMARIZER_PROMPT),
+        ChatMessage::user(transcript),
+    ]
+}
+
+fn describe(message: &ChatMessage) -> String {
+    let role = format!("{:?}", message.role).to_lowercase();
+    let mut text = format!("[{}]", role);
+    if let Some(content) = &message.content {
+        text.push('\n');
+        text.push_str(&excerpt(&content.as_text()));
+        for _ in 0..content.image_count() {
+            text.push_str("\n[image]");
+        }
+    }
+    for call in message.tool_calls.iter().flatten() {
+        text.push_str(&format!(
+            "\ncalled {}({})",
+            call.function.name,
+            excerpt(&call.function.arguments)
+        ));
+    }
+    text
+}
+
+fn excerpt(text: &str) -> String {
+    if text.chars().count() <= MAX_EXCERPT_CHARS {
+        return text.to_string();
+    }
+    let head: String = text.chars().take(MAX_EXCERPT_CHARS).collect();
+    format!("{}\n[... truncated]", head)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// System prompt, then user and assistant turns, the second of which
+    /// calls a tool.
+    fn transcript() -> Vec<ChatMessage> {
+        vec![
+            ChatMessage::system("system"),
+            ChatMessage::user("first question"),
+            ChatMessage::assistant("first answer"),
+            ChatMessage::user("second question"),
+            ChatMessage::assistant("reading a file"),
+            ChatMessage::tool_result("call_1", "file contents"),
+            ChatMessage::assistant("second answer"),
+        ]
+    }
+
+    #[test]
+    fn plans_to_keep_the_recent_turns() {
+        let messages = transcript();
+
+        assert_eq!(plan(&messages, None, 2), Some(4));
+        // Tool results stay with the reply that asked for them.
+        assert_eq!(plan(&messages, None, 3), Some(3));
+        assert_eq!(plan(&messages, None, 0), Some(6));
+    }
+
+    #[test]
+    fn plans_nothing_when_there_is_nothing_new_to_compact() {
+        let messages = transcript();
+        let previous = Compaction {
+            through: 4,
+            summary: "so far".to_string(),
+        };
+
+        // Keeping five turns keeps everything after the system prompt.
+        assert_eq!(plan(&messages, None, 5), None);
+        assert_eq!(plan(&messages, None, 6), None);
+        assert_eq!(plan(&messages, Some(&previous), 2), None);
+        assert_eq!(plan(&messages, Some(&previous), 1), Some(6));
+    }
+
+    #[test]
+    fn asks_to_fold_the_messages_into_the_previous_summary() {
+        let previous = Compaction {
+            through: 3,
+            summary: "The user asked a first question.".to_string(),
+        };
+        let messages = transcript();
+
+        let request = summary_request(Some(&previous), &messages[3..6]);
+
+        assert_eq!(request.len(), 2);
+        let text = request[1].text_content().unwrap();
+        assert!(
+            text.starts_with(
+                "[earlier summary]\nThe user asked a first question.\n\n[user]\nsecond question"
+            ),
+            "{}",
+            text
+        );
+        assert!(text.contains("[tool]\nfile contents"), "{}", text);
+    }
+
+    #[test]
+    fn truncates_long_messages() {
+        let long = "x".repeat(MAX_EXCERPT_CHARS + 10);
+
+        let text = describe(&ChatMessage::user(long));
+
+        assert!(text.ends_with("\n[... truncated]"));
+        assert_eq!(text.matches('x').count(), MAX_EXCERPT_CHARS);
+    }
+}
@@ -0,0 +1,452 @@
+use crate::error::Error;
+use anyhow::{Context, Result, anyhow};
+use globset::GlobBuilder;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::ignore_rules::IgnoreRules;
+use crate::sandbox::sanitize_and_resolve_path;
+
+/// Size caps applied when attaching files to a prompt.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ContextLimits {
+    /// Files larger than this are truncated.
+    pub max_file_bytes: usize,
+    /// Once this much has been attached, remaining files are skipped.
+    pub max_total_bytes: usize,
+    /// Images larger than this are refused.
+    pub max_image_bytes: usize,
+    /// Approximate token budget for `--repo-map` outlines.
+    pub repo_map_tokens: usize,
+}
+
+impl Default for ContextLimits {
+    fn default() -> Self {
+        ContextLimits {
+            max_file_bytes: 100 * 1024,
+            max_total_bytes: 400 * 1024,
+            max_image_bytes: 5 * 1024 * 1024,
+            repo_map_tokens: 2_048,
+        }
+    }
+}
+
+/// Where an attached block of text came from.
+pub enum ContextSource {
+    /// A file, by its path relative to the workspace.
+    File(String),
+    /// Text piped into junior on stdin.
+    Stdin,
+    /// An outline of the workspace from [`crate::repo_map`].
+    RepoMap,
+}
+
+/// A file or other text attached to the conversation.
+pub struct ContextFile {
+    pub source: ContextSource,
+    pub contents: String,
+    pub truncated: bool,
+}
+
+impl ContextFile {
+    fn label(&self) -> String {
+        match &self.source {
+            ContextSource::File(path) => format!("File: {}", path),
+            ContextSource::Stdin => "Piped input:".to_string(),
+            ContextSource::RepoMap => "Repository map:".to_string(),
+        }
+    }
+
+    fn language(&self) -> &'static str {
+        match &self.source {
+            ContextSource::File(path) => language_for(path),
+            ContextSource::Stdin | ContextSource::RepoMap => "",
+        }
+    }
+}
+
+/// Files gathered for a prompt, along with notes about anything that was
+/// skipped or cut short.
+#[derive(Default)]
+pub struct GatheredContext {
+    pub files: Vec<ContextFile>,
+    pub notes: Vec<String>,
+}
+
+impl GatheredContext {
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.notes.is_empty()
+    }
+
+    /// Workspace-relative paths of the attached files.
+    pub fn paths(&self) -> Vec<String> {
+        self.files
+            .iter()
+            .filter_map(|f| match &f.source {
+                ContextSource::File(path) => Some(path.clone()),
+                ContextSource::Stdin | ContextSource::RepoMap => None,
+            })
+            .collect()
+    }
+
+    /// Attaches text, truncating it or skipping it when it would exceed
+    /// `limits`.
+    pub fn push(&mut self, source: ContextSource, mut contents: String, limits: ContextLimits) {
+        let label = match &source {
+            ContextSource::File(path) => path.clone(),
+            ContextSource::Stdin => "stdin".to_string(),
+            ContextSource::RepoMap => "repository map".to_string(),
+        };
+
+        let truncated = contents.len() > limits.max_file_bytes;
+        if truncated {
+            contents.truncate(floor_char_boundary(&contents, limits.max_file_bytes));
+            self.notes.push(format!(
+                "{} truncated to {} bytes",
+                label, limits.max_file_bytes
+            ));
+        }
+        let total: usize = self.files.iter().map(|f| f.contents.len()).sum();
+        if total + contents.len() > limits.max_total_bytes {
+            self.notes.push(format!(
+                "{} skipped: total context limit of {} bytes reached",
+                label, limits.max_total_bytes
+            ));
+            return;
+        }
+
+        self.files.push(ContextFile {
+            source,
+            contents,
+            truncated,
+        });
+    }
+
+    /// Each file as a labeled, fenced block, followed by the notes.
+    pub fn render(&self) -> String {
+        let mut blocks: Vec<String> = self
+            .files
+            .iter()
+            .map(|file| {
+                let fence = fence_for(&file.contents);
+                let mut block = format!(
+                    "{}\n{}{}\n{}",
+                    file.label(),
+                    fence,
+                    file.language(),
+                    file.contents
+                );
+                if !file.contents.ends_with('\n') {
+                    block.push('\n');
+                }
+                block.push_str(&fence);
+                if file.truncated {
+                    block.push_str("\n(truncated)");
+                }
+                block
+            })
+            .collect();
+        if !self.notes.is_empty() {
+            let notes: Vec<String> = self.notes.iter().map(|n| format!("- {}", n)).collect();
+            blocks.push(format!("Notes:\n{}", notes.join("\n")));
+        }
+        blocks.join("\n\n")
+    }
+}
+
+/// Reads the files named by `specs` and `mentions`, each of which may be a
+/// file, a directory (read recursively) or a glob relative to the
+/// workspace. Directories and globs skip ignored files; a file named
+/// explicitly is always read.
+pub fn gather(
+    workspace: &Path,
+    specs: &[String],
+    mentions: &[String],
+    limits: ContextLimits,
+    ignore: &IgnoreRules,
+) -> Result<GatheredContext> {
+    let root = workspace.canonicalize()?;
+    let mut seen = HashSet::new();
+    let mut paths = Vec::new();
+    for spec in specs {
+        paths.extend(expand(&root, spec, ignore)?);
+    }
+    for mention in mentions {
+        paths.extend(
+            expand(&root, mention, ignore)
+                .with_context(|| format!("Could not resolve mention @{}", mention))?,
+        );
+    }
+    paths.retain(|path| seen.insert(path.clone()));
+
+    let mut context = GatheredContext::default();
+    for path in paths {
+        let relative = relative_path(&root, &path);
+        let bytes =
+            fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        match as_text(bytes) {
+            Some(contents) => context.push(ContextSource::File(relative), contents, limits),
+            None => context
+                .notes
+                .push(format!("{} skipped: binary file", relative)),
+        }
+    }
+
+    Ok(context)
+}
+
+/// The `@path` mentions in a prompt, in order and without duplicates. A
+/// mention is an `@` at the start of a word; anything inside backticks is
+/// left alone, and trailing punctuation is not part of the path.
+pub fn mentions(prompt: &str) -> Vec<String> {
+    let mention = Regex::new(r"(?:^|\s)@([\w.~/*?\[\]{},-][^\s`]*)").unwrap();
+    let mut found: Vec<String> = Vec::new();
+    for segment in outside_code(prompt) {
+        for capture in mention.captures_iter(segment) {
+            let path = capture[1].trim_end_matches(['.', ',', ';', ':', '!', '?', ')', '"', '\'']);
+            if looks_like_path(path) && !found.iter().any(|f| f == path) {
+                found.push(path.to_string());
+            }
+        }
+    }
+    found
+}
+
+/// Whether a mention names a path rather than, say, a `@user` or an
+/// `@Override`: it has a directory, an extension or a glob in it.
+fn looks_like_path(mention: &str) -> bool {
+    mention.contains(['/', '.', '*', '?', '['])
+        && mention.chars().any(|c| c.is_alphanumeric() || c == '*')
+}
+
+/// The parts of `text` outside code spans and fenced blocks. As in
+/// Markdown, a run of backticks is closed by the next run of the same
+/// length, and a run that is never closed is plain text.
+fn outside_code(text: &str) -> Vec<&str> {
+    let mut segments = Vec::new();
+    let mut rest = text;
+    while let Some(open) = rest.find('`') {
+        segments.push(&rest[..open]);
+        let run = backtick_run(&rest[open..]);
+        let after = &rest[open + run..];
+        let mut search = 0;
+        let mut close = None;
+        while let Some(i) = after[search..].find('`') {
+            let start = search + i;
+            let len = backtick_run(&after[start..]);
+            if len == run {
+                close = Some(start + len);
+                break;
+            }
+            search = start + len;
+        }
+        rest = match close {
+            Some(end) => &after[end..],
+            None => after,
+        };
+    }
+    segments.push(rest);
+    segments
+}
+
+fn backtick_run(text: &str) -> usize {
+    text.len() - text.trim_start_matches('`').len()
+}
+
+fn expand(root: &Path, spec: &str, ignore: &IgnoreRules) -> Result<Vec<PathBuf>> {
+    if spec.contains(['*', '?', '[', '{']) {
+        let glob = GlobBuilder::new(spec.trim_start_matches("./"))
+            .literal_separator(true)
+            .build()
+            .with_context(|| format!("Invalid glob '{}'", spec))?
+            .compile_matcher();
+        let matches: Vec<PathBuf> = ignore
+            .walk(root)?
+            .into_iter()
+            .filter(|path| glob.is_match(path.strip_prefix(root).unwrap_or(path)))
+            .collect();
+        if matches.is_empty() {
+            return Err(anyhow!("No files match '{}'", spec));
+        }
+        return Ok(matches);
+    }
+
+    let path = sanitize_and_resolve_path(root, spec)?;
+    // The lexical check above cannot see symlinks along the way.
+    if let Ok(target) = path.canonicalize()
+        && !target.starts_with(root)
+    {
+        return Err(Error::Sandbox(format!(
+            "Unsafe path: '{}' leads to '{}', outside of working directory '{}'",
+            spec,
+            target.display(),
+            root.display()
+        ))
+        .into());
+    }
+    if path.is_dir() {
+        ignore.walk(&path)
+    } else if path.is_file() {
+        Ok(vec![path])
+    } else {
+        Err(anyhow!("No such file or directory: {}", spec))
+    }
+}
+
+fn relative_path(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Decodes file contents as text, or `None` if they look binary.
+pub(crate) fn as_text(bytes: Vec<u8>) -> Option<String> {
+    if bytes.iter().take(8 * 1024).any(|&b| b == 0) {
+        return None;
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn floor_char_boundary(s: &str, index: usize) -> usize {
+    (0..=index.min(s.len()))
+        .rev()
+        .find(|&i| s.is_char_boundary(i))
+        .unwrap_or(0)
+}
+
+/// A fence longer than any run of backticks inside `contents`.
+fn fence_for(contents: &str) -> String {
+    let longest = contents
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or(0);
+    "`".repeat(longest.max(2) + 1)
+}
+
+/// The Markdown language tag for a file, based on its extension.
+pub fn language_for(path: &str) -> &'static str {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("");
+    match extension {
+        "rs" => "rust",
+        "py" => "python",
+        "js" | "mjs" | "cjs" => "javascript",
+        "ts" => "typescript",
+        "tsx" => "tsx",
+        "jsx" => "jsx",
+        "go" => "go",
+        "java" => "java",
+        "kt" => "kotlin",
+        "c" | "h" => "c",
+        "cpp" | "cc" | "hpp" => "cpp",
+        "cs" => "csharp",
+        "rb" => "ruby",
+        "php" => "php",
+        "swift" => "swift",
+        "sh" | "bash" => "bash",
+        "md" => "markdown",
+        "json" => "json",
+        "toml" => "toml",
+        "yaml" | "yml" => "yaml",
+        "html" => "html",
+        "css" => "css",
+        "sql" => "sql",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_mentions_in_order_without_duplicates() {
+        assert_eq!(
+            mentions("Compare @src/a.rs with @docs/ and @src/a.rs, then @*.md."),
+            ["src/a.rs", "docs/", "*.md"]
+        );
+        assert_eq!(mentions("@README.md first"), ["README.md"]);
+    }
+
+    #[test]
+    fn skips_words_that_are_not_paths() {
+        assert!(mentions("Ask @user about @Override, mail me@example.com").is_empty());
+        assert!(mentions("@@ -1,3 +1,4 @@ fn main() {").is_empty());
+        assert!(mentions("Trailing @...").is_empty());
+    }
+
+    #[test]
+    fn leaves_code_spans_and_blocks_alone() {
+        assert_eq!(
+            mentions("Use `@decorator.py` like ``a ` @b.rs`` in @c.rs"),
+            ["c.rs"]
+        );
+        assert_eq!(
+            mentions("```\n@inside.rs\n```\nand @outside.rs"),
+            ["outside.rs"]
+        );
+        // An unclosed run of backticks is plain text.
+        assert_eq!(mentions("a ` stray @tick.rs"), ["tick.rs"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn attaching_a_directory_leaves_symlinks_out() {
+        let outside = tempfile::tempdir().unwrap();
+        fs::write(outside.path().join("secret.txt"), "secret").unwrap();
+        let workspace = tempfile::tempdir().unwrap();
+        let root = workspace.path();
+        fs::write(root.join("a.rs"), "fn a() {}").unwrap();
+        std::os::unix::fs::symlink(outside.path(), root.join("link")).unwrap();
+        std::os::unix::fs::symlink(root, root.join("loop")).unwrap();
+        let ignore = IgnoreRules::load(root).unwrap();
+
+        let context = gather(
+            root,
+            &[".".to_string()],
+            &[],
+            ContextLimits::default(),
+            &ignore,
+        )
+        .unwrap();
+
+        assert_eq!(context.paths(), ["a.rs"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_explicit_paths_through_symlinks_out_of_the_workspace() {
+        let outside = tempfile::tempdir().unwrap();
+        fs::write(outside.path().join("secret.txt"), "secret").unwrap();
+        let workspace = tempfile::tempdir().unwrap();
+        let root = workspace.path();
+        std::os::unix::fs::symlink(outside.path(), root.join("link")).unwrap();
+        let ignore = IgnoreRules::load(root).unwrap();
+
+        for spec in ["link", "link/secret.txt"] {
+            let Err(error) = gather(
+                root,
+                &[spec.to_string()],
+                &[],
+                ContextLimits::default(),
+                &ignore,
+            ) else {
+                panic!("{} was attached", spec);
+            };
+            assert!(
+                matches!(Error::find(&error), Some(Error::Sandbox(_))),
+                "{}: {:#}",
+                spec,
+                error
+            );
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use reqwest::StatusCode;
+use serde::Deserialize;
+use thiserror::Error;
+
+/// Failures that callers may want to tell apart, each with its own exit
+/// code. Anything else stays an `anyhow` error and exits with 1.
+///
+/// Functions keep returning `anyhow::Result`; find the class of a failure
+/// with [`Error::find`].
+#[derive(Debug, Error)]
+pub enum Error {
+    /// Invalid settings, or a command line that cannot be carried out.
+    #[error("{0}")]
+    Config(String),
+    #[error("The provider rejected the API key ({status}): {message}")]
+    Auth { status: StatusCode, message: String },
+    #[error("Rate limited by the provider: {message}")]
+    RateLimited { message: String },
+    #[error("The conversation does not fit the model's context window: {message}")]
+    ContextLength { message: String },
+    #[error("The provider's content filter blocked the request: {message}")]
+    ContentFilter { message: String },
+    /// The endpoint could not be reached, timed out, or answered with a
+    /// server error.
+    #[error("The provider is unavailable: {message}")]
+    Unavailable { message: String },
+    /// Any other error response.
+    #[error("The provider returned an error ({status}): {message}")]
+    Provider { status: StatusCode, message: String },
+    /// A path outside the workspace, or one excluded by ignore rules.
+    #[error("{0}")]
+    Sandbox(String),
+    #[error("Tool {tool} failed: {message}")]
+    Tool { tool: String, message: String },
+    #[error("Cancelled by the user")]
+    Cancelled,
+}
+
+impl Error {
+    /// The process exit code for this class of failure. These are part of
+    /// the CLI's interface; see the README before changing them.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Config(_) => 2,
+            Error::Auth { .. } => 3,
+            Error::RateLimited { .. } => 4,
+            Error::ContextLength { .. } => 5,
+            Error::ContentFilter { .. } => 6,
+            Error::Unavailable { .. } => 7,
+            Error::Provider { .. } => 8,
+            Error::Sandbox(_) => 9,
+            Error::Tool { .. } => 10,
+            Error::Cancelled => 130,
+        }
+    }
+
+    /// What the user can do about it, if there is something to suggest.
+    pub fn hint(&self) -> Option<&'static str> {
+        Some(match self {
+            Error::Auth { .. } => {
+                "Check api_key or api_key_cmd in ~/.junior.toml, $JUNIOR_API_KEY, and the http.auth scheme"
+            }
+            Error::RateLimited { .. } => {
+                "Wait and try again, raise http.retry.max_retries, or configure a fallback profile"
+            }
+            Error::ContextLength { .. } => {
+                "Attach fewer or smaller files, lower max_tokens, or set budget.on_overflow = \"trim\""
+            }
+            Error::ContentFilter { .. } => "Rephrase the prompt or leave out the flagged content",
+            Error::Unavailable { .. } => {
+                "Check the endpoint, proxy and network, or configure a fallback profile"
+            }
+            _ => return None,
+        })
+    }
+
+    /// Whether another attempt, or another backend, might succeed.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Error::RateLimited { .. } | Error::Unavailable { .. })
+    }
+
+    /// The first typed error in `error`'s chain.
+    pub fn find(error: &anyhow::Error) -> Option<&Error> {
+        error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<Error>())
+    }
+
+    /// Classifies an error response from the provider by its status and
+    /// the error object in its body. OpenAI, Azure and Anthropic style
+    /// bodies are understood; anything else is classified by status.
+    pub fn from_response(status: StatusCode, body: &str) -> Self {
+        let detail = ProviderError::parse(body);
+        let message = detail.message.clone().unwrap_or_else(|| {
+            let body = body.trim();
+            if body.is_empty() {
+                status.to_string()
+            } else {
+                body.to_string()
+            }
+        });
+        let is = |codes: &[&str]| detail.codes().any(|code| codes.contains(&code));
+        let lower = message.to_lowercase();
+
+        if is(&["context_length_exceeded", "string_above_max_length"])
+            || lower.contains("maximum context length")
+            || lower.contains("context window")
+            || lower.contains("prompt is too long")
+        {
+            Error::ContextLength { message }
+        } else if is(&[
+            "content_filter",
+            "content_policy_violation",
+            "ResponsibleAIPolicyViolation",
+        ]) {
+            Error::ContentFilter { message }
+        } else if status == StatusCode::UNAUTHORIZED
+            || status == StatusCode::FORBIDDEN
+            || is(&[
+                "invalid_api_key",
+                "invalid_authentication",
+                "authentication_error",
+                "permission_error",
+            ])
+        {
+            Error::Auth { status, message }
+        } else if is(&["insufficient_quota"]) {
+            // Sent with a 429, but waiting does not help until the plan or
+            // billing is changed, so it is not retried.
+            Error::Provider { status, message }
+        } else if status == StatusCode::TOO_MANY_REQUESTS
+            || is(&["rate_limit_exceeded", "rate_limit_error"])
+        {
+            Error::RateLimited { message }
+        } else if status == StatusCode::REQUEST_TIMEOUT
+            || status.is_server_error()
+            || is(&["overloaded_error", "server_error"])
+        {
+            Error::Unavailable {
+                message: format!("{} - {}", status, message),
+            }
+        } else {
+            Error::Provider { status, message }
+        }
+    }
+}
+
+/// The error object providers put in error responses:
+/// `{"error": {"message", "type", "code"}}`, with Azure adding an inner
+/// error code. Some servers put a message or detail at the top level.
+#[derive(Debug, Default, Deserialize)]
+struct ProviderError {
+    message: Option<String>,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    code: Option<serde_json::Value>,
+    innererror: Option<Box<ProviderError>>,
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    error: Option<ErrorField>,
+    message: Option<String>,
+    detail: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ErrorField {
+    Object(ProviderError),
+    Message(String),
+}
+
+impl ProviderError {
+    fn parse(body: &str) -> Self {
+        let Ok(body) = serde_json::from_str::<ErrorBody>(body) else {
+            return Self::default();
+        };
+        match body.error {
+            Some(ErrorField::Object(error)) => error,
+            Some(ErrorField::Message(message)) => ProviderError {
+                message: Some(message),
+                ..Self::default()
+            },
+            None => ProviderError {
+                message: body.message.or(body.detail),
+                ..Self::default()
+            },
+        }
+    }
+
+    /// The type and code, and those of the inner error.
+    fn codes(&self) -> impl Iterator<Item = &str> {
+        let code = self.code.as_ref().and_then(|code| code.as_str());
+        let inner = self.innererror.iter().flat_map(|inner| {
+            [
+                inner.kind.as_deref(),
+                inner.code.as_ref().and_then(|c| c.as_str()),
+            ]
+        });
+        [self.kind.as_deref(), code]
+            .into_iter()
+            .chain(inner)
+            .flatten()
+    }
+}
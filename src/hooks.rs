@@ -0,0 +1,308 @@
+use anyhow::{Context, Result, anyhow};
+use globset::Glob;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// Shell commands run around tool calls.
+///
+/// ```toml
+/// [[hooks.pre_tool]]
+/// tools = ["write_file", "create_file"]
+/// paths = ["src/generated/**"]
+/// command = "./scripts/check-policy.sh"
+///
+/// [[hooks.post_tool]]
+/// paths = ["**/*.rs"]
+/// command = "rustfmt \"$JUNIOR_PATH\""
+/// append_output = true
+/// ```
+#[derive(Default, Debug, Clone, Deserialize, Serialize)]
+pub struct Hooks {
+    /// Run before a tool. A non-zero exit vetoes the call and its output is
+    /// sent to the model as the reason.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pre_tool: Vec<Hook>,
+    /// Run after a tool succeeds.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_tool: Vec<Hook>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Hook {
+    /// Command run with `sh -c` in the workspace. The tool call is written
+    /// to its stdin as JSON, and `JUNIOR_TOOL` and `JUNIOR_PATH` are set.
+    pub command: String,
+    /// Tool names the hook applies to. Empty matches every tool.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<String>,
+    /// Globs, relative to the workspace, matched against the call's path
+    /// arguments. Empty matches every call.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub paths: Vec<String>,
+    /// Append the hook's output to the tool result (post-tool hooks only).
+    #[serde(default)]
+    pub append_output: bool,
+}
+
+/// A tool call as seen by hooks.
+pub struct HookCall<'a> {
+    pub tool: &'a str,
+    pub arguments: &'a Value,
+    /// Absolute paths the call touches.
+    pub paths: &'a [PathBuf],
+    pub workspace: &'a Path,
+}
+
+struct HookOutput {
+    success: bool,
+    text: String,
+}
+
+impl Hook {
+    fn matches(&self, call: &HookCall) -> Result<bool> {
+        if !self.tools.is_empty() && !self.tools.iter().any(|t| t == call.tool) {
+            return Ok(false);
+        }
+        if self.paths.is_empty() {
+            return Ok(true);
+        }
+        for pattern in &self.paths {
+            let glob = Glob::new(pattern)
+                .with_context(|| format!("Invalid hook path glob '{}'", pattern))?
+                .compile_matcher();
+            let matched = call
+                .paths
+                .iter()
+                .any(|path| glob.is_match(path.strip_prefix(call.workspace).unwrap_or(path)));
+            if matched {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    async fn run(&self, call: &HookCall<'_>, input: &Value) -> Result<HookOutput> {
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(&self.command)
+            .current_dir(call.workspace)
+            .env("JUNIOR_TOOL", call.tool)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if let Some(path) = call.paths.first() {
+            command.env("JUNIOR_PATH", path);
+        }
+
+        let mut child = command
+            .spawn()
+            .with_context(|| format!("Failed to start hook `{}`", self.command))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            // A hook that never reads its input may close stdin early.
+            let _ = stdin.write_all(input.to_string().as_bytes()).await;
+        }
+        let output = child.wait_with_output().await?;
+
+        let mut text = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !stderr.trim().is_empty() {
+            if !text.is_empty() {
+                text.push('\n');
+            }
+            text.push_str(stderr.trim());
+        }
+
+        Ok(HookOutput {
+            success: output.status.success(),
+            text,
+        })
+    }
+}
+
+impl Hooks {
+    /// Runs the matching pre-tool hooks, failing with the hook's message if
+    /// any of them vetoes the call.
+    pub async fn before(&self, call: &HookCall<'_>) -> Result<()> {
+        let input = json!({
+            "tool": call.tool,
+            "arguments": call.arguments,
+        });
+        for hook in &self.pre_tool {
+            if !hook.matches(call)? {
+                continue;
+            }
+            let output = hook.run(call, &input).await?;
+            if !output.success {
+                let reason = if output.text.is_empty() {
+                    "no reason given".to_string()
+                } else {
+                    output.text
+                };
+                return Err(anyhow!("Blocked by hook `{}`: {}", hook.command, reason));
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the matching post-tool hooks and returns the tool output with
+    /// any hook output that should be reported appended.
+    pub async fn after(&self, call: &HookCall<'_>, tool_output: String) -> Result<String> {
+        let input = json!({
+            "tool": call.tool,
+            "arguments": call.arguments,
+            "output": tool_output,
+        });
+        let mut result = tool_output;
+        for hook in &self.post_tool {
+            if !hook.matches(call)? {
+                continue;
+            }
+            let output = hook.run(call, &input).await?;
+            if !output.success {
+                result.push_str(&format!(
+                    "\n\nPost-tool hook `{}` failed:\n{}",
+                    hook.command, output.text
+                ));
+            } else if hook.append_output && !output.text.is_empty() {
+                result.push_str(&format!(
+                    "\n\nOutput of `{}`:\n{}",
+                    hook.command, output.text
+                ));
+            }
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hook(command: &str, tools: &[&str], paths: &[&str]) -> Hook {
+        Hook {
+            command: command.to_string(),
+            tools: tools.iter().map(|t| t.to_string()).collect(),
+            paths: paths.iter().map(|p| p.to_string()).collect(),
+            append_output: false,
+        }
+    }
+
+    fn call<'a>(tool: &'a str, paths: &'a [PathBuf], workspace: &'a Path) -> HookCall<'a> {
+        HookCall {
+            tool,
+            arguments: &Value::Null,
+            paths,
+            workspace,
+        }
+    }
+
+    #[test]
+    fn matches_tool_names_and_path_globs() {
+        let workspace = Path::new("/ws");
+        let generated = [workspace.join("src/generated/api.rs")];
+        let readme = [workspace.join("README.md")];
+
+        let any = hook("true", &[], &[]);
+        assert!(any.matches(&call("read_file", &[], workspace)).unwrap());
+
+        let writes = hook("true", &["write_file", "create_file"], &[]);
+        assert!(
+            writes
+                .matches(&call("create_file", &readme, workspace))
+                .unwrap()
+        );
+        assert!(
+            !writes
+                .matches(&call("read_file", &readme, workspace))
+                .unwrap()
+        );
+
+        let scoped = hook("true", &["write_file"], &["src/generated/**", "*.toml"]);
+        assert!(
+            scoped
+                .matches(&call("write_file", &generated, workspace))
+                .unwrap()
+        );
+        assert!(
+            !scoped
+                .matches(&call("write_file", &readme, workspace))
+                .unwrap()
+        );
+        assert!(!scoped.matches(&call("write_file", &[], workspace)).unwrap());
+        assert!(
+            !scoped
+                .matches(&call("read_file", &generated, workspace))
+                .unwrap()
+        );
+
+        let invalid = hook("true", &[], &["src/[oops"]);
+        let error = invalid
+            .matches(&call("write_file", &readme, workspace))
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Invalid hook path glob 'src/[oops'");
+    }
+
+    #[tokio::test]
+    async fn appends_output_only_when_asked() {
+        let workspace = tempfile::tempdir().unwrap();
+        let paths = [workspace.path().join("a.rs")];
+        let call = call("write_file", &paths, workspace.path());
+        let quiet = hook("echo formatted", &[], &[]);
+        let loud = Hook {
+            append_output: true,
+            ..hook(
+                "echo \"checked $JUNIOR_TOOL on $(basename $JUNIOR_PATH)\"",
+                &[],
+                &[],
+            )
+        };
+        let hooks = Hooks {
+            post_tool: vec![quiet, loud.clone()],
+            ..Hooks::default()
+        };
+
+        let output = hooks.after(&call, "Wrote a.rs".to_string()).await.unwrap();
+
+        assert_eq!(
+            output,
+            format!(
+                "Wrote a.rs\n\nOutput of `{}`:\nchecked write_file on a.rs",
+                loud.command
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn pre_hooks_read_the_call_from_stdin() {
+        let workspace = tempfile::tempdir().unwrap();
+        let call = HookCall {
+            tool: "delete_file",
+            arguments: &json!({ "path": "keep.txt" }),
+            paths: &[],
+            workspace: workspace.path(),
+        };
+        let hooks = Hooks {
+            pre_tool: vec![
+                hook(
+                    "grep -q '\"path\":\"keep.txt\"' && exit 3; exit 0",
+                    &[],
+                    &[],
+                ),
+                hook("exit 1", &["write_file"], &[]),
+            ],
+            ..Hooks::default()
+        };
+
+        let error = hooks.before(&call).await.unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Blocked by hook `grep -q '\"path\":\"keep.txt\"' && exit 3; exit 0`: no reason given"
+        );
+    }
+}
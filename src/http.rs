@@ -0,0 +1,190 @@
+use anyhow::{Context, Result, anyhow};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, RETRY_AFTER};
+use reqwest::{Certificate, Client, Proxy};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// How the API key is sent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuthScheme {
+    /// `Authorization: Bearer <key>`, as OpenAI and most compatible
+    /// servers expect.
+    #[default]
+    Bearer,
+    /// `api-key: <key>`, as Azure OpenAI expects.
+    ApiKey,
+    /// No key is sent, e.g. when a gateway adds its own credentials.
+    None,
+}
+
+/// How requests reach the endpoint: extra headers, proxy, trusted
+/// certificates, timeouts, authentication and retries. Read from the
+/// `[http]` table, or `[profiles.<name>.http]` for one profile.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    /// Headers added to every request. `$VAR` and `${VAR}` in values are
+    /// replaced with environment variables, so tokens can stay out of
+    /// config files.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    /// Proxy for all requests, e.g. `http://proxy.corp:3128`. Without it,
+    /// the `HTTPS_PROXY` and `HTTP_PROXY` environment variables are used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    /// PEM file of extra CA certificates to trust, e.g. for a corporate
+    /// gateway that re-signs TLS traffic.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ca_bundle: Option<String>,
+    /// Limit on connecting to the endpoint; 0 waits as long as the
+    /// operating system does.
+    pub connect_timeout_secs: u64,
+    /// Limit on a whole request, including reading the response; 0 waits
+    /// forever.
+    pub timeout_secs: u64,
+    pub auth: AuthScheme,
+    /// Sent as the `api-version` query parameter, as Azure OpenAI
+    /// requires.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_version: Option<String>,
+    pub retry: RetryConfig,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            headers: BTreeMap::new(),
+            proxy: None,
+            ca_bundle: None,
+            connect_timeout_secs: 10,
+            // Long enough for a large completion that is not streamed.
+            timeout_secs: 600,
+            auth: AuthScheme::default(),
+            api_version: None,
+            retry: RetryConfig::default(),
+        }
+    }
+}
+
+impl HttpConfig {
+    /// Builds the client every request of a run goes through.
+    pub fn build_client(&self) -> Result<Client> {
+        let mut builder = Client::builder().default_headers(self.header_map()?);
+        if let Some(proxy) = &self.proxy {
+            builder = builder
+                .proxy(Proxy::all(proxy).with_context(|| format!("Invalid proxy '{}'", proxy))?);
+        }
+        if let Some(path) = self.ca_bundle_path()? {
+            let pem = fs::read(&path)
+                .with_context(|| format!("Failed to read CA bundle {}", path.display()))?;
+            let certificates = Certificate::from_pem_bundle(&pem)
+                .with_context(|| format!("Invalid CA bundle {}", path.display()))?;
+            if certificates.is_empty() {
+                return Err(anyhow!(
+                    "CA bundle {} contains no PEM certificates",
+                    path.display()
+                ));
+            }
+            for certificate in certificates {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+        if self.connect_timeout_secs > 0 {
+            builder = builder.connect_timeout(Duration::from_secs(self.connect_timeout_secs));
+        }
+        if self.timeout_secs > 0 {
+            builder = builder.timeout(Duration::from_secs(self.timeout_secs));
+        }
+        builder.build().context("Failed to set up the HTTP client")
+    }
+
+    pub fn ca_bundle_path(&self) -> Result<Option<PathBuf>> {
+        self.ca_bundle
+            .as_deref()
+            .map(|path| Ok(PathBuf::from(shellexpand::full(path)?.to_string())))
+            .transpose()
+    }
+
+    fn header_map(&self) -> Result<HeaderMap> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            let header = HeaderName::try_from(name.as_str())
+                .with_context(|| format!("Invalid header name '{}'", name))?;
+            let value = shellexpand::env(value)
+                .with_context(|| format!("Failed to expand header {}", name))?;
+            let mut value = HeaderValue::try_from(value.as_ref())
+                .with_context(|| format!("Invalid value for header {}", name))?;
+            value.set_sensitive(true);
+            headers.insert(header, value);
+        }
+        Ok(headers)
+    }
+}
+
+/// Retrying requests that fail with 408, 429 or a 5xx status, or that
+/// cannot connect or time out. Delays double from `initial_backoff_ms` up
+/// to `max_backoff_ms`, with random jitter so that clients hitting the
+/// same limit do not retry in lockstep. A `Retry-After` header replaces
+/// the computed delay.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RetryConfig {
+    /// Retries after the first attempt; 0 disables retrying.
+    pub max_retries: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    /// Longest `Retry-After` that is waited for. Longer waits fail the
+    /// request instead.
+    pub max_retry_after_secs: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            max_retries: 3,
+            initial_backoff_ms: 1_000,
+            max_backoff_ms: 30_000,
+            max_retry_after_secs: 120,
+        }
+    }
+}
+
+impl RetryConfig {
+    /// How long to wait before retry number `retry` (counting from 0), or
+    /// `None` when no retries are left or the server asks for a longer
+    /// wait than allowed.
+    pub fn delay(&self, retry: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        if retry >= self.max_retries {
+            return None;
+        }
+        if let Some(wait) = retry_after {
+            return (wait <= Duration::from_secs(self.max_retry_after_secs)).then_some(wait);
+        }
+        let backoff = self
+            .initial_backoff_ms
+            .saturating_mul(1 << retry.min(20))
+            .min(self.max_backoff_ms);
+        // Equal jitter: at least half the backoff, at most all of it.
+        Some(Duration::from_millis(
+            backoff / 2 + fastrand::u64(0..=backoff - backoff / 2),
+        ))
+    }
+}
+
+/// The wait a `Retry-After` header asks for, given either as seconds or
+/// as an HTTP date.
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
@@ -0,0 +1,236 @@
+use anyhow::Result;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Project-specific ignore file, using `.gitignore` syntax. Its patterns
+/// take precedence over `.gitignore` in the same directory.
+pub const JUNIORIGNORE: &str = ".juniorignore";
+
+/// The paths junior leaves alone: everything matched by `.gitignore` files,
+/// `.git/info/exclude`, the global git excludes file and `.juniorignore`
+/// files, plus `.git` itself.
+///
+/// Context gathering and tools share one instance so they always agree on
+/// what is ignored.
+pub struct IgnoreRules {
+    /// The repository root, or the workspace when it is not in a repository.
+    root: PathBuf,
+    /// Rules that apply to the whole tree, lowest precedence.
+    base: Vec<Gitignore>,
+    /// Per-directory `.juniorignore` and `.gitignore` matchers, loaded on
+    /// first use.
+    directories: Mutex<HashMap<PathBuf, Vec<Gitignore>>>,
+}
+
+impl IgnoreRules {
+    pub fn load(workspace: &Path) -> Result<Self> {
+        let workspace = workspace.canonicalize()?;
+        let repo_root = workspace
+            .ancestors()
+            .find(|dir| dir.join(".git").exists())
+            .map(Path::to_path_buf);
+        let root = repo_root.clone().unwrap_or_else(|| workspace.clone());
+
+        let mut base = Vec::new();
+        let (global, _) = GitignoreBuilder::new(&root).build_global();
+        base.push(global);
+        if let Some(repo_root) = repo_root {
+            // Patterns in the exclude file are relative to the repository
+            // root, not to `.git/info`.
+            let mut exclude = GitignoreBuilder::new(&repo_root);
+            exclude.add(repo_root.join(".git").join("info").join("exclude"));
+            base.push(exclude.build()?);
+        }
+
+        Ok(IgnoreRules {
+            root,
+            base,
+            directories: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Rules that ignore nothing.
+    pub fn none() -> Self {
+        IgnoreRules {
+            root: PathBuf::new(),
+            base: Vec::new(),
+            directories: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether an absolute path is ignored. Paths outside the rules' root
+    /// are never ignored.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        if self.root.as_os_str().is_empty() {
+            return false;
+        }
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return false;
+        };
+        if relative.components().any(|c| c.as_os_str() == ".git") {
+            return true;
+        }
+
+        // Deeper directories override shallower ones, so check them first.
+        let dirs = path
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| dir.starts_with(&self.root));
+        for dir in dirs {
+            let verdict = self.with_matchers(dir, |matchers| {
+                matchers
+                    .iter()
+                    .map(|m| m.matched_path_or_any_parents(path, is_dir))
+                    .find(|m| !m.is_none())
+                    .map(|m| m.is_ignore())
+            });
+            if let Some(ignored) = verdict {
+                return ignored;
+            }
+        }
+
+        self.base
+            .iter()
+            .map(|m| m.matched_path_or_any_parents(path, is_dir))
+            .find(|m| !m.is_none())
+            .is_some_and(|m| m.is_ignore())
+    }
+
+    /// Every file under `dir` that is not ignored, in a stable order.
+    /// Symlinks are skipped: they could lead out of the workspace, or back
+    /// into it in a loop.
+    pub fn walk(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        let mut entries: Vec<_> = fs::read_dir(dir)?.flatten().collect();
+        entries.sort_by_key(|entry| entry.path());
+        for entry in entries {
+            let file_type = entry.file_type()?;
+            let path = entry.path();
+            if file_type.is_symlink() || self.is_ignored(&path, file_type.is_dir()) {
+                continue;
+            }
+            if file_type.is_dir() {
+                files.extend(self.walk(&path)?);
+            } else if file_type.is_file() {
+                files.push(path);
+            }
+        }
+        Ok(files)
+    }
+
+    fn with_matchers<T>(&self, dir: &Path, f: impl FnOnce(&[Gitignore]) -> T) -> T {
+        let mut cache = self.directories.lock().unwrap();
+        let matchers = cache.entry(dir.to_path_buf()).or_insert_with(|| {
+            [JUNIORIGNORE, ".gitignore"]
+                .iter()
+                .map(|name| dir.join(name))
+                .filter(|path| path.is_file())
+                .map(|path| Gitignore::new(path).0)
+                .collect()
+        });
+        f(matchers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(path: &Path, contents: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn applies_the_repository_exclude_file() {
+        let repo = tempfile::tempdir().unwrap();
+        let root = repo.path().canonicalize().unwrap();
+        write(&root.join(".git/info/exclude"), "secret.txt\n");
+        write(&root.join("src/a.rs"), "");
+
+        let rules = IgnoreRules::load(&root.join("src")).unwrap();
+
+        assert!(rules.is_ignored(&root.join("secret.txt"), false));
+        assert!(rules.is_ignored(&root.join("src/secret.txt"), false));
+        assert!(!rules.is_ignored(&root.join("src/a.rs"), false));
+        assert!(rules.is_ignored(&root.join(".git/config"), false));
+    }
+
+    #[test]
+    fn deeper_and_juniorignore_rules_take_precedence() {
+        let repo = tempfile::tempdir().unwrap();
+        let root = repo.path().canonicalize().unwrap();
+        fs::create_dir(root.join(".git")).unwrap();
+        write(&root.join(".gitignore"), "*.log\nbuild/\n");
+        write(&root.join("keep/.gitignore"), "!*.log\n");
+        write(&root.join("docs/.gitignore"), "draft.md\n");
+        write(&root.join("docs/.juniorignore"), "!draft.md\nnotes.md\n");
+
+        let rules = IgnoreRules::load(&root).unwrap();
+
+        assert!(rules.is_ignored(&root.join("debug.log"), false));
+        assert!(!rules.is_ignored(&root.join("keep/debug.log"), false));
+        assert!(rules.is_ignored(&root.join("build/out.o"), false));
+        assert!(!rules.is_ignored(&root.join("docs/draft.md"), false));
+        assert!(rules.is_ignored(&root.join("docs/notes.md"), false));
+        assert!(!rules.is_ignored(&root.join("docs/readme.md"), false));
+    }
+
+    #[test]
+    fn walks_only_files_that_are_not_ignored() {
+        let repo = tempfile::tempdir().unwrap();
+        let root = repo.path().canonicalize().unwrap();
+        fs::create_dir(root.join(".git")).unwrap();
+        write(&root.join(".gitignore"), "target/\n");
+        write(&root.join("target/out"), "");
+        write(&root.join("src/b.rs"), "");
+        write(&root.join("src/a.rs"), "");
+
+        let files = IgnoreRules::load(&root).unwrap().walk(&root).unwrap();
+
+        let names: Vec<_> = files
+            .iter()
+            .map(|f| f.strip_prefix(&root).unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                Path::new(".gitignore"),
+                Path::new("src/a.rs"),
+                Path::new("src/b.rs")
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn walks_past_symlinks_out_of_the_tree_and_loops() {
+        let outside = tempfile::tempdir().unwrap();
+        write(&outside.path().join("secret.txt"), "secret");
+        let repo = tempfile::tempdir().unwrap();
+        let root = repo.path().canonicalize().unwrap();
+        write(&root.join("a.rs"), "");
+        std::os::unix::fs::symlink(outside.path(), root.join("link")).unwrap();
+        std::os::unix::fs::symlink(&root, root.join("loop")).unwrap();
+        std::os::unix::fs::symlink(outside.path().join("secret.txt"), root.join("s.txt")).unwrap();
+
+        let files = IgnoreRules::load(&root).unwrap().walk(&root).unwrap();
+
+        assert_eq!(files, [root.join("a.rs")]);
+    }
+
+    #[test]
+    fn paths_outside_the_root_are_never_ignored() {
+        let repo = tempfile::tempdir().unwrap();
+        let root = repo.path().canonicalize().unwrap();
+        write(&root.join(".gitignore"), "*\n");
+
+        let rules = IgnoreRules::load(&root).unwrap();
+
+        assert!(!rules.is_ignored(Path::new("/elsewhere/file"), false));
+        assert!(!IgnoreRules::none().is_ignored(&root.join("file"), false));
+    }
+}
@@ -0,0 +1,109 @@
+use anyhow::{Context, Result, anyhow};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use std::fs;
+use std::path::Path;
+
+use crate::message::ContentPart;
+
+/// Identifies a supported image format from its first bytes, so that a
+/// misnamed file is neither rejected nor sent with the wrong type.
+pub fn media_type(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
+
+/// Reads a PNG, JPEG or WebP file into an image part holding a base64
+/// `data:` URL.
+pub fn load_image(path: &Path, max_bytes: usize) -> Result<ContentPart> {
+    let size = fs::metadata(path)
+        .with_context(|| format!("Failed to read image {}", path.display()))?
+        .len();
+    if size > max_bytes as u64 {
+        return Err(anyhow!(
+            "Image {} is {} bytes, over the {}-byte limit set by [context] max_image_bytes",
+            path.display(),
+            size,
+            max_bytes
+        ));
+    }
+    let bytes =
+        fs::read(path).with_context(|| format!("Failed to read image {}", path.display()))?;
+    let media_type = media_type(&bytes)
+        .ok_or_else(|| anyhow!("{} is not a PNG, JPEG or WebP image", path.display()))?;
+    Ok(ContentPart::image(format!(
+        "data:{};base64,{}",
+        media_type,
+        STANDARD.encode(&bytes)
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    #[test]
+    fn recognizes_images_by_their_bytes() {
+        assert_eq!(media_type(PNG), Some("image/png"));
+        assert_eq!(media_type(&[0xFF, 0xD8, 0xFF, 0xE0]), Some("image/jpeg"));
+        assert_eq!(media_type(b"RIFF\0\0\0\0WEBPVP8 "), Some("image/webp"));
+        assert_eq!(media_type(b"RIFF\0\0\0\0WAVE"), None);
+        assert_eq!(media_type(b"GIF89a"), None);
+        assert_eq!(media_type(b""), None);
+    }
+
+    #[test]
+    fn loads_an_image_as_a_data_url_whatever_its_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("screenshot.txt");
+        fs::write(&path, PNG).unwrap();
+
+        let ContentPart::ImageUrl { image_url } = load_image(&path, 1024).unwrap() else {
+            panic!("not an image part");
+        };
+
+        assert_eq!(
+            image_url.url,
+            format!("data:image/png;base64,{}", STANDARD.encode(PNG))
+        );
+    }
+
+    #[test]
+    fn refuses_large_and_unsupported_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let large = dir.path().join("large.png");
+        fs::write(&large, PNG).unwrap();
+        let gif = dir.path().join("anim.gif");
+        fs::write(&gif, b"GIF89a").unwrap();
+
+        let error = load_image(&large, 8).unwrap_err().to_string();
+        assert!(
+            error.contains("is 16 bytes, over the 8-byte limit"),
+            "{}",
+            error
+        );
+
+        let error = load_image(&gif, 1024).unwrap_err().to_string();
+        assert!(
+            error.ends_with("anim.gif is not a PNG, JPEG or WebP image"),
+            "{}",
+            error
+        );
+
+        let missing = load_image(&dir.path().join("missing.png"), 1024).unwrap_err();
+        assert!(
+            missing.to_string().starts_with("Failed to read image"),
+            "{}",
+            missing
+        );
+    }
+}
@@ -0,0 +1,415 @@
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::context::as_text;
+use crate::ignore_rules::IgnoreRules;
+use crate::prompt::JUNIOR_DIR;
+
+/// Directory under `.junior` that holds the index.
+const INDEX_DIR: &str = "index";
+const INDEX_FILE: &str = "index.json";
+
+/// Lines per chunk, and lines shared by consecutive chunks so that code
+/// spanning a boundary is still found whole in one of them.
+const CHUNK_LINES: usize = 40;
+const CHUNK_OVERLAP: usize = 10;
+
+/// Files larger than this are not indexed.
+const MAX_FILE_BYTES: u64 = 1024 * 1024;
+
+/// BM25 parameters: term frequency saturation and length normalization.
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+
+/// A lexical index of the workspace, stored in `.junior/index`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct CodeIndex {
+    /// Indexed files by path relative to the workspace.
+    pub files: BTreeMap<String, IndexedFile>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct IndexedFile {
+    pub modified: SystemTime,
+    pub size: u64,
+    pub chunks: Vec<Chunk>,
+}
+
+/// A run of lines and how often each term occurs in it.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Chunk {
+    /// First and last line, counting from 1.
+    pub start_line: usize,
+    pub end_line: usize,
+    pub length: usize,
+    pub terms: HashMap<String, u32>,
+}
+
+/// What an update changed.
+#[derive(Debug, Default)]
+pub struct IndexStats {
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+}
+
+/// A chunk that matched a search.
+#[derive(Debug)]
+pub struct SearchHit {
+    pub path: String,
+    pub start_line: usize,
+    pub end_line: usize,
+    pub score: f64,
+}
+
+impl CodeIndex {
+    /// Directory holding the index of `workspace`.
+    pub fn directory(workspace: &Path) -> PathBuf {
+        workspace.join(JUNIOR_DIR).join(INDEX_DIR)
+    }
+
+    /// The index of `workspace`, or an empty one if it has not been built.
+    pub fn load(workspace: &Path) -> Result<Self> {
+        let path = Self::directory(workspace).join(INDEX_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let json = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&json).with_context(|| {
+            format!(
+                "Failed to parse {}; run `junior index --rebuild`",
+                path.display()
+            )
+        })
+    }
+
+    pub fn save(&self, workspace: &Path) -> Result<()> {
+        let dir = Self::directory(workspace);
+        fs::create_dir_all(&dir)?;
+        // Keeps the index out of git and out of junior's own file walks.
+        fs::write(dir.join(".gitignore"), "*\n")?;
+        fs::write(dir.join(INDEX_FILE), serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.files.values().map(|f| f.chunks.len()).sum()
+    }
+
+    /// Re-indexes files whose modification time or size changed since the
+    /// last update, and drops files that are gone or now ignored.
+    pub fn update(&mut self, workspace: &Path, ignore: &IgnoreRules) -> Result<IndexStats> {
+        let root = workspace.canonicalize()?;
+        let index_dir = Self::directory(&root);
+        let mut stats = IndexStats::default();
+        let mut present = HashSet::new();
+
+        for path in ignore.walk(&root)? {
+            if path.starts_with(&index_dir) {
+                continue;
+            }
+            let metadata = fs::metadata(&path)?;
+            if metadata.len() > MAX_FILE_BYTES {
+                continue;
+            }
+            let relative = path
+                .strip_prefix(&root)
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .to_string();
+            let modified = metadata.modified()?;
+
+            if let Some(existing) = self.files.get(&relative)
+                && existing.modified == modified
+                && existing.size == metadata.len()
+            {
+                present.insert(relative);
+                stats.unchanged += 1;
+                continue;
+            }
+
+            let Some(text) = as_text(fs::read(&path)?) else {
+                continue;
+            };
+            let file = IndexedFile {
+                modified,
+                size: metadata.len(),
+                chunks: chunk(&relative, &text),
+            };
+            if self.files.insert(relative.clone(), file).is_some() {
+                stats.updated += 1;
+            } else {
+                stats.added += 1;
+            }
+            present.insert(relative);
+        }
+
+        let before = self.files.len();
+        self.files.retain(|path, _| present.contains(path));
+        stats.removed = before - self.files.len();
+        Ok(stats)
+    }
+
+    /// The best chunks for `query` by BM25 score, limited to files under
+    /// `prefix` (a workspace-relative path, empty for all files).
+    pub fn search(&self, query: &str, prefix: &str, limit: usize) -> Vec<SearchHit> {
+        let terms = tokenize(query);
+        let total = self.chunk_count();
+        if terms.is_empty() || total == 0 {
+            return Vec::new();
+        }
+        let chunks = || {
+            self.files
+                .iter()
+                .flat_map(|(path, file)| file.chunks.iter().map(move |c| (path, c)))
+        };
+        let average_length = chunks().map(|(_, c)| c.length).sum::<usize>() as f64 / total as f64;
+        let idf: HashMap<&str, f64> = terms
+            .iter()
+            .map(|term| {
+                let df = chunks().filter(|(_, c)| c.terms.contains_key(term)).count() as f64;
+                let idf = (1.0 + (total as f64 - df + 0.5) / (df + 0.5)).ln();
+                (term.as_str(), idf)
+            })
+            .collect();
+
+        let mut hits: Vec<SearchHit> = chunks()
+            .filter(|(path, _)| under(path, prefix))
+            .filter_map(|(path, chunk)| {
+                let norm = K1 * (1.0 - B + B * chunk.length as f64 / average_length.max(1.0));
+                let score: f64 = idf
+                    .iter()
+                    .filter_map(|(term, idf)| {
+                        let tf = *chunk.terms.get(*term)? as f64;
+                        Some(idf * tf * (K1 + 1.0) / (tf + norm))
+                    })
+                    .sum();
+                (score > 0.0).then(|| SearchHit {
+                    path: path.clone(),
+                    start_line: chunk.start_line,
+                    end_line: chunk.end_line,
+                    score,
+                })
+            })
+            .collect();
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hits.truncate(limit);
+        hits
+    }
+}
+
+/// Finds the workspace whose index covers `path`: the nearest ancestor with
+/// a `.junior/index` directory.
+pub fn find_index_root(path: &Path) -> Result<PathBuf> {
+    path.ancestors()
+        .find(|dir| CodeIndex::directory(dir).join(INDEX_FILE).is_file())
+        .map(Path::to_path_buf)
+        .ok_or_else(|| anyhow!("No codebase index found; run `junior index` first"))
+}
+
+fn under(path: &str, prefix: &str) -> bool {
+    prefix.is_empty()
+        || path == prefix
+        || path
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+fn chunk(path: &str, text: &str) -> Vec<Chunk> {
+    let lines: Vec<&str> = text.lines().collect();
+    let path_terms = tokenize(path);
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    loop {
+        let end = (start + CHUNK_LINES).min(lines.len());
+        let mut terms: HashMap<String, u32> = HashMap::new();
+        for term in lines[start..end]
+            .iter()
+            .flat_map(|line| tokenize(line))
+            .chain(path_terms.iter().cloned())
+        {
+            *terms.entry(term).or_default() += 1;
+        }
+        chunks.push(Chunk {
+            start_line: start + 1,
+            end_line: end.max(start + 1),
+            length: terms.values().map(|&n| n as usize).sum(),
+            terms,
+        });
+        if end >= lines.len() {
+            return chunks;
+        }
+        start = end - CHUNK_OVERLAP;
+    }
+}
+
+/// Lowercased words and identifiers, with `snake_case` and `camelCase`
+/// identifiers also split into their parts so that "parse config" finds
+/// `parseConfig` and `parse_config`.
+pub fn tokenize(text: &str) -> Vec<String> {
+    let mut terms = Vec::new();
+    for word in text.split(|c: char| !c.is_alphanumeric() && c != '_') {
+        let parts = split_identifier(word);
+        if parts.len() > 1 {
+            terms.push(word.to_lowercase());
+        }
+        terms.extend(parts.into_iter().filter(|p| p.len() >= 2));
+    }
+    terms
+}
+
+fn split_identifier(word: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut previous_lower = false;
+    for c in word.chars() {
+        if (c == '_' || (c.is_uppercase() && previous_lower)) && !current.is_empty() {
+            parts.push(std::mem::take(&mut current));
+        }
+        if c != '_' {
+            current.extend(c.to_lowercase());
+        }
+        previous_lower = c.is_lowercase() || c.is_ascii_digit();
+    }
+    if !current.is_empty() {
+        parts.push(current);
+    }
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(files: &[(&str, &str)]) -> CodeIndex {
+        let mut index = CodeIndex::default();
+        for (path, text) in files {
+            index.files.insert(
+                path.to_string(),
+                IndexedFile {
+                    modified: SystemTime::UNIX_EPOCH,
+                    size: text.len() as u64,
+                    chunks: chunk(path, text),
+                },
+            );
+        }
+        index
+    }
+
+    #[test]
+    fn tokenizes_identifiers_and_their_parts() {
+        assert_eq!(
+            tokenize("fn parseConfig(raw_toml: &str)"),
+            [
+                "fn",
+                "parseconfig",
+                "parse",
+                "config",
+                "raw_toml",
+                "raw",
+                "toml",
+                "str"
+            ]
+        );
+        assert_eq!(tokenize("a + b2"), ["b2"]);
+    }
+
+    #[test]
+    fn chunks_overlap() {
+        let text: String = (1..=100).map(|i| format!("line{}\n", i)).collect();
+
+        let chunks = chunk("notes.txt", &text);
+
+        let ranges: Vec<_> = chunks.iter().map(|c| (c.start_line, c.end_line)).collect();
+        assert_eq!(ranges, [(1, 40), (31, 70), (61, 100)]);
+        assert_eq!(chunks[0].terms["notes"], 1);
+        assert_eq!(chunk("empty.txt", "")[0].start_line, 1);
+    }
+
+    #[test]
+    fn ranks_rare_and_frequent_terms_higher() {
+        let index = index(&[
+            (
+                "src/config.rs",
+                "fn load_config() {}\nfn parse_config() {}\nconfig config",
+            ),
+            ("src/agent.rs", "fn run() {}\nlet config = load();"),
+            ("src/llm.rs", "fn send() {}\nfn retry() {}"),
+            ("docs/config.md", "How to write the config file"),
+        ]);
+
+        let hits = index.search("parse config", "", 10);
+
+        let paths: Vec<&str> = hits.iter().map(|hit| hit.path.as_str()).collect();
+        assert_eq!(paths[0], "src/config.rs");
+        assert!(!paths.contains(&"src/llm.rs"));
+        assert!(hits.windows(2).all(|w| w[0].score >= w[1].score));
+    }
+
+    #[test]
+    fn limits_results_to_a_prefix() {
+        let index = index(&[
+            ("src/config.rs", "config"),
+            ("src/configure/mod.rs", "config"),
+            ("docs/config.md", "config"),
+        ]);
+
+        let paths = |prefix, limit| -> Vec<String> {
+            let mut paths: Vec<_> = index
+                .search("config", prefix, limit)
+                .into_iter()
+                .map(|hit| hit.path)
+                .collect();
+            paths.sort();
+            paths
+        };
+
+        assert_eq!(paths("src/configure", 10), ["src/configure/mod.rs"]);
+        assert_eq!(paths("src", 10), ["src/config.rs", "src/configure/mod.rs"]);
+        assert_eq!(paths("", 1).len(), 1);
+        assert!(index.search("", "", 10).is_empty());
+    }
+
+    #[test]
+    fn updates_only_changed_files() {
+        let workspace = tempfile::tempdir().unwrap();
+        let root = workspace.path();
+        fs::write(root.join("a.rs"), "fn alpha() {}").unwrap();
+        fs::write(root.join("b.rs"), "fn beta() {}").unwrap();
+        let ignore = IgnoreRules::load(root).unwrap();
+        let mut index = CodeIndex::default();
+
+        let stats = index.update(root, &ignore).unwrap();
+        assert_eq!((stats.added, stats.unchanged), (2, 0));
+
+        fs::write(root.join("b.rs"), "fn beta_two() {}").unwrap();
+        fs::remove_file(root.join("a.rs")).unwrap();
+        let stats = index.update(root, &ignore).unwrap();
+        assert_eq!((stats.updated, stats.removed), (1, 1));
+        assert_eq!(index.search("two", "", 10)[0].path, "b.rs");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn does_not_follow_symlink_loops() {
+        let workspace = tempfile::tempdir().unwrap();
+        let root = workspace.path();
+        fs::write(root.join("a.rs"), "fn alpha() {}").unwrap();
+        std::os::unix::fs::symlink(root, root.join("loop")).unwrap();
+        let ignore = IgnoreRules::load(root).unwrap();
+
+        let stats = CodeIndex::default().update(root, &ignore).unwrap();
+
+        assert_eq!(stats.added, 1);
+    }
+}
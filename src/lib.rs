@@ -0,0 +1,33 @@
+//! Junior drives an OpenAI-compatible chat model that can operate on files
+//! in a workspace.
+//!
+//! The CLI is a thin wrapper over this crate; other programs can build an
+//! [`Agent`], register their own [`Tool`]s and inspect the resulting
+//! [`Session`] transcript directly.
+
+pub mod agent;
+pub mod cancel;
+pub mod commands;
+pub mod compaction;
+pub mod config;
+pub mod context;
+pub mod error;
+pub mod hooks;
+pub mod http;
+pub mod ignore_rules;
+pub mod image;
+pub mod index;
+pub mod llm;
+pub mod memory;
+pub mod message;
+pub mod prompt;
+pub mod repo_map;
+pub mod sandbox;
+pub mod session;
+pub mod tokens;
+pub mod tools;
+
+pub use agent::{Agent, AgentEvent};
+pub use message::{ChatMessage, Content, ContentPart, Role, ToolCall};
+pub use session::Session;
+pub use tools::{Tool, ToolDefinition, ToolRegistry};
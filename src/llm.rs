@@ -0,0 +1,325 @@
+use anyhow::{Result, anyhow};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use std::time::Duration;
+
+use crate::error::Error;
+use crate::http::{AuthScheme, HttpConfig, RetryConfig, retry_after};
+use crate::message::ChatMessage;
+use crate::tools::ToolDefinition;
+
+/// Sampling settings sent with each request. Unset values are left to the
+/// provider's defaults.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct GenerationParams {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    /// Sequences at which the model stops generating.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    /// Whether the model may request several tool calls in one response.
+    /// Only sent when tools are.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parallel_tool_calls: Option<bool>,
+    /// `none`, `auto`, `required`, or the name of a tool the model must
+    /// call. Only sent when tools are; defaults to `auto`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<String>,
+    /// Provider-specific fields added to the request body as they are,
+    /// e.g. `extra_body = { reasoning_effort = "low" }`.
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub extra_body: Map<String, Value>,
+}
+
+impl GenerationParams {
+    /// The settings for the requests that follow the first one of a
+    /// prompt. A tool choice that forces a call only applies to the first
+    /// request; later ones are left to `auto` so the model can answer.
+    pub fn for_follow_up(&self) -> GenerationParams {
+        GenerationParams {
+            tool_choice: self.tool_choice.clone().filter(|choice| choice == "none"),
+            ..self.clone()
+        }
+    }
+
+    /// The `tool_choice` value for a request offering `tools`: one of the
+    /// keywords as is, or a tool name as a function object.
+    fn tool_choice_value(&self, tools: &[ToolDefinition]) -> Result<Value> {
+        let choice = self.tool_choice.as_deref().unwrap_or("auto");
+        if matches!(choice, "none" | "auto" | "required") {
+            return Ok(Value::String(choice.to_string()));
+        }
+        if !tools.iter().any(|tool| tool.function.name == choice) {
+            return Err(anyhow!(
+                "tool_choice '{}' is not one of none, auto, required or an offered tool",
+                choice
+            ));
+        }
+        Ok(json!({ "type": "function", "function": { "name": choice } }))
+    }
+}
+
+/// An OpenAI-compatible chat completions endpoint, the credentials for it
+/// and the client requests to it are sent with.
+#[derive(Debug, Clone)]
+pub struct Backend {
+    /// Recorded in the session for each answer, e.g. the profile name.
+    pub name: String,
+    pub endpoint: String,
+    pub api_key: String,
+    pub auth: AuthScheme,
+    pub api_version: Option<String>,
+    pub retry: RetryConfig,
+    /// Shared by every request so that connections are reused.
+    pub client: Client,
+}
+
+impl Backend {
+    /// A backend with the default [`HttpConfig`]: bearer auth, default
+    /// retries and the default connect and request timeouts.
+    pub fn new(endpoint: impl Into<String>, api_key: impl Into<String>) -> Self {
+        let http = HttpConfig::default();
+        let client = http
+            .build_client()
+            .expect("the default HTTP settings are valid");
+        Self::with_client(endpoint, api_key, &http, client)
+    }
+
+    /// A backend whose client is built from `http`.
+    pub fn with_http(
+        endpoint: impl Into<String>,
+        api_key: impl Into<String>,
+        http: &HttpConfig,
+    ) -> Result<Self> {
+        Ok(Self::with_client(
+            endpoint,
+            api_key,
+            http,
+            http.build_client()?,
+        ))
+    }
+
+    fn with_client(
+        endpoint: impl Into<String>,
+        api_key: impl Into<String>,
+        http: &HttpConfig,
+        client: Client,
+    ) -> Self {
+        Backend {
+            name: "default".to_string(),
+            endpoint: endpoint.into(),
+            api_key: api_key.into(),
+            auth: http.auth,
+            api_version: http.api_version.clone(),
+            retry: http.retry.clone(),
+            client,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ChatRequest {
+    model: String,
+    messages: Vec<ChatMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<i64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<ToolDefinition>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parallel_tool_calls: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct ChatResponse {
+    choices: Vec<ChatChoice>,
+}
+
+#[derive(Deserialize)]
+struct ChatChoice {
+    message: ChatMessage,
+    finish_reason: Option<String>,
+}
+
+/// A failed attempt, and how long the server asked to wait before the
+/// next one.
+struct Failure {
+    error: anyhow::Error,
+    retry_after: Option<Duration>,
+}
+
+impl Failure {
+    fn retryable(&self) -> bool {
+        Error::find(&self.error).is_some_and(Error::is_retryable)
+    }
+}
+
+impl<E: Into<anyhow::Error>> From<E> for Failure {
+    fn from(error: E) -> Self {
+        Failure {
+            error: error.into(),
+            retry_after: None,
+        }
+    }
+}
+
+/// Sends one chat completion request, retrying failures that may be
+/// temporary as `backend.retry` allows. `on_retry` is told about each
+/// retry before waiting for it. Provider errors are returned as an
+/// [`Error`] of the matching class.
+pub async fn send_to_llm(
+    backend: &Backend,
+    messages: &[ChatMessage],
+    model: &str,
+    tool_definitions: &[ToolDefinition],
+    params: &GenerationParams,
+    on_retry: &(dyn Fn(&str) + Sync),
+) -> Result<ChatMessage> {
+    let offers_tools = !tool_definitions.is_empty();
+    let request_body = ChatRequest {
+        model: model.to_string(),
+        messages: messages.to_vec(),
+        temperature: params.temperature,
+        top_p: params.top_p,
+        max_tokens: params.max_tokens,
+        stop: params.stop.clone(),
+        seed: params.seed,
+        tools: tool_definitions.to_vec(),
+        tool_choice: offers_tools
+            .then(|| params.tool_choice_value(tool_definitions))
+            .transpose()?,
+        parallel_tool_calls: params.parallel_tool_calls.filter(|_| offers_tools),
+    };
+    // Extra fields are added last so that they can also replace a field
+    // junior sets itself.
+    let mut request_body = serde_json::to_value(request_body)?;
+    if let Value::Object(body) = &mut request_body {
+        body.extend(params.extra_body.clone());
+    }
+
+    let mut retry = 0;
+    loop {
+        let failure = match send_once(backend, &request_body).await {
+            Ok(message) => return Ok(message),
+            Err(failure) => failure,
+        };
+        let delay = failure
+            .retryable()
+            .then(|| backend.retry.delay(retry, failure.retry_after))
+            .flatten();
+        let Some(delay) = delay else {
+            return Err(failure.error);
+        };
+        retry += 1;
+        on_retry(&format!(
+            "{}; retrying in {:.1}s ({} of {})",
+            first_line(&failure.error),
+            delay.as_secs_f64(),
+            retry,
+            backend.retry.max_retries
+        ));
+        tokio::time::sleep(delay).await;
+    }
+}
+
+async fn send_once(backend: &Backend, request_body: &Value) -> Result<ChatMessage, Failure> {
+    let mut request = backend.client.post(&backend.endpoint);
+    if let Some(version) = &backend.api_version {
+        request = request.query(&[("api-version", version)]);
+    }
+    request = match backend.auth {
+        AuthScheme::Bearer => request.bearer_auth(&backend.api_key),
+        AuthScheme::ApiKey => request.header("api-key", &backend.api_key),
+        AuthScheme::None => request,
+    };
+    let response = request
+        .json(request_body)
+        .send()
+        .await
+        .map_err(transport_error)?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let retry_after = retry_after(response.headers());
+        let text = response.text().await.unwrap_or_default();
+        return Err(Failure {
+            error: Error::from_response(status, &text).into(),
+            retry_after,
+        });
+    }
+
+    let response_json: ChatResponse = response.json().await.map_err(transport_error)?;
+    let choice = response_json
+        .choices
+        .into_iter()
+        .next()
+        .ok_or(anyhow!("No response from model"))?;
+    if choice.finish_reason.as_deref() == Some("content_filter")
+        && choice.message.content.is_none()
+        && choice.message.tool_calls.is_none()
+    {
+        return Err(Error::ContentFilter {
+            message: "the response was withheld".to_string(),
+        }
+        .into());
+    }
+
+    Ok(choice.message)
+}
+
+/// Connection failures and timeouts mean the provider is unavailable;
+/// anything else, such as a malformed response, is not classified.
+fn transport_error(error: reqwest::Error) -> Failure {
+    let endpoint = error
+        .url()
+        .map_or_else(|| "the provider".to_string(), |url| url.to_string());
+    let message = if error.is_timeout() {
+        format!("the request to {} timed out", endpoint)
+    } else if error.is_connect() {
+        format!("could not connect to {}: {}", endpoint, root_cause(&error))
+    } else {
+        return error.into();
+    };
+    Error::Unavailable { message }.into()
+}
+
+/// The innermost source of an error, such as "Connection refused",
+/// without the OS error number.
+fn root_cause(error: &(dyn std::error::Error + 'static)) -> String {
+    let mut cause = error;
+    while let Some(source) = cause.source() {
+        cause = source;
+    }
+    let text = cause.to_string();
+    match text.find(" (os error ") {
+        Some(end) => text[..end].to_string(),
+        None => text,
+    }
+}
+
+/// The first line of an error, short enough for a notice.
+fn first_line(error: &anyhow::Error) -> String {
+    let text = error.to_string();
+    let line = text.lines().next().unwrap_or_default();
+    if line.chars().count() > 120 {
+        format!("{}…", line.chars().take(120).collect::<String>())
+    } else {
+        line.to_string()
+    }
+}
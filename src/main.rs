@@ -1,348 +1,646 @@
-use anyhow::{Result, anyhow};
-use clap::{Arg, Command};
-use confy;
-use reqwest::Client;
-use serde::{Deserialize, Serialize};
+use anyhow::{Context, Result, anyhow};
+use clap::{Arg, ArgAction, ArgMatches, Command};
 use serde_json::Value;
-use shellexpand;
+use std::collections::HashMap;
 use std::env;
-use std::fs::{self, File};
-use std::io::Read;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, IsTerminal, Read, Write};
 use std::path::{Path, PathBuf};
-use std::time::{SystemTime, UNIX_EPOCH};
-mod tools;
-use tools::tool_definitions;
-
-use crate::tools::{ToolDefinition, tools_registry};
-
-#[derive(Default, Debug, Deserialize, Serialize)]
-struct Config {
-    api_key: String,
-    model: String,
-    endpoint: String,
-    history_directory_path: String,
-}
-
-#[derive(Serialize, Deserialize, Clone, Debug)]
-#[serde(rename_all = "lowercase")]
-enum Role {
-    System,
-    User,
-    Assistant,
-}
-
-#[derive(Serialize, Deserialize, Clone, Debug)]
-struct ToolCall {
-    id: String,
-    #[serde(rename = "type")]
-    tool_type: String,
-    function: FunctionCall,
-}
-
-#[derive(Serialize, Deserialize, Clone, Debug)]
-struct FunctionCall {
-    name: String,
-    arguments: String,
-}
-
-#[derive(Serialize, Deserialize, Clone)]
-struct ChatMessage {
-    role: Role,
-    content: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    tool_calls: Option<Vec<ToolCall>>,
-}
-
-#[derive(Serialize)]
-struct ChatRequest {
-    model: String,
-    messages: Vec<ChatMessage>,
-    tools: Vec<ToolDefinition>,
-    tool_choice: String,
-}
-
-#[derive(Deserialize)]
-struct ChatResponse {
-    choices: Vec<ChatChoice>,
-}
-
-#[derive(Deserialize)]
-struct ChatChoice {
-    message: ChatMessage,
+use std::sync::Arc;
+
+use junior::cancel::CancelToken;
+use junior::commands::find_command;
+use junior::config::{
+    Config, ConfigFlags, PROJECT_CONFIG_FILES, config_from_layers, config_path,
+    create_session_file, describe_config, find_project_config, load_config, load_fallbacks,
+    load_layers, set_value, write_config_file,
+};
+use junior::context::{ContextSource, gather, mentions};
+use junior::error::Error;
+use junior::ignore_rules::IgnoreRules;
+use junior::image::load_image;
+use junior::index::CodeIndex;
+use junior::prompt::load_system_prompt;
+use junior::repo_map;
+use junior::tools::ToolRegistry;
+use junior::{Agent, AgentEvent, ChatMessage, ContentPart};
+
+fn print_event(event: &AgentEvent) {
+    match event {
+        AgentEvent::AssistantMessage(content) => println!("{}", content),
+        AgentEvent::ToolCall { name, args } => {
+            println!("[Tool Call] {} with args: {}", name, args)
+        }
+        AgentEvent::ToolOutput { output, .. } => println!("[Tool Output] {}", output),
+        AgentEvent::ToolError { error, .. } => {
+            eprintln!("❌ Error executing tool call: {}", error)
+        }
+        AgentEvent::Notice(notice) => eprintln!("{}", notice),
+    }
 }
 
-#[derive(Serialize)]
-struct ChatSessionLog {
-    model: String,
-    tools: Vec<ToolDefinition>,
-    messages: Vec<ChatMessage>,
+fn parse_vars(matches: &ArgMatches) -> Result<HashMap<String, String>> {
+    matches
+        .get_many::<String>("var")
+        .unwrap_or_default()
+        .map(|var| {
+            let (key, value) = var
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Invalid --var '{}': expected KEY=VALUE", var))?;
+            Ok((key.to_string(), value.to_string()))
+        })
+        .collect()
 }
 
-fn load_config() -> Result<Configuration> {
-    let config_path = dirs::home_dir()
-        .ok_or(anyhow!("Failed to find home directory"))?
-        .join(".junior.toml");
-    let config: Config = confy::load_path(config_path)?;
-    let history_path =
-        PathBuf::from(shellexpand::full(&config.history_directory_path)?.to_string());
-    Ok(Configuration {
-        api_key: config.api_key,
-        endpoint: config.endpoint,
-        log_file: create_session_file(&history_path)?,
-        model: config.model,
-    })
+/// `--extra KEY=JSON` values. A value that is not valid JSON is sent as a
+/// string, so `--extra reasoning_effort=low` works without quoting.
+fn parse_extra(matches: &ArgMatches) -> Result<Vec<(String, Value)>> {
+    matches
+        .get_many::<String>("extra")
+        .unwrap_or_default()
+        .map(|extra| {
+            let (key, value) = extra
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Invalid --extra '{}': expected KEY=JSON", extra))?;
+            let value = serde_json::from_str(value).unwrap_or_else(|_| Value::from(value));
+            Ok((key.to_string(), value))
+        })
+        .collect()
 }
 
-fn create_session_file(history_path: &PathBuf) -> Result<PathBuf> {
-    fs::create_dir_all(&history_path)?;
-    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
-    Ok(history_path.join(format!("session-{}.json", timestamp)))
+/// Cancels the run on the first Ctrl-C and exits on the second.
+fn handle_ctrl_c(cancel: Arc<CancelToken>) {
+    tokio::spawn(async move {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            if cancel.is_cancelled() {
+                eprintln!();
+                std::process::exit(130);
+            }
+            eprintln!("\nCancelling... press Ctrl-C again to exit immediately");
+            cancel.cancel();
+        }
+    });
 }
 
-async fn send_to_llm(
-    messages: &[ChatMessage],
-    model: &str,
-    endpoint: &str,
-    api_key: &str,
-    tool_definitions: &[ToolDefinition],
-) -> Result<ChatMessage> {
-    let client = Client::new();
-
-    let request_body = ChatRequest {
-        model: model.to_string(),
-        messages: messages.to_vec(),
-        tools: tool_definitions.to_vec(),
-        tool_choice: "auto".to_string(),
+/// Asks on the terminal whether a tool call may run. The terminal is
+/// opened directly so that approval works even when stdin is piped.
+fn approve_tool_call(name: &str, args: &Value) -> bool {
+    let Ok(mut tty) = OpenOptions::new().read(true).write(true).open("/dev/tty") else {
+        eprintln!("Refused {}: no terminal to ask for approval", name);
+        return false;
     };
-
-    let response = client
-        .post(endpoint)
-        .bearer_auth(api_key)
-        .json(&request_body)
-        .send()
-        .await?;
-
-    if !response.status().is_success() {
-        let status = response.status();
-        let text = response.text().await?;
-        return Err(anyhow!("Request failed with status: {} - {}", status, text));
+    if write!(tty, "Allow {} with {}? [y/N] ", name, args).is_err() {
+        return false;
     }
-
-    let response_json: ChatResponse = response.json().await?;
-    let message = response_json
-        .choices
-        .get(0)
-        .ok_or(anyhow!("No response from model"))?
-        .message
-        .clone();
-
-    Ok(message)
+    let mut answer = String::new();
+    if BufReader::new(tty).read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim(), "y" | "Y" | "yes")
 }
 
-fn save_log(log: &ChatSessionLog, path: &PathBuf) -> Result<()> {
-    let json = serde_json::to_string_pretty(log)?;
-    fs::write(path, json)?;
-    Ok(())
+/// Reads stdin when it is piped or redirected rather than a terminal.
+fn read_piped_stdin() -> Result<Option<String>> {
+    let mut stdin = io::stdin();
+    if stdin.is_terminal() {
+        return Ok(None);
+    }
+    let mut input = String::new();
+    stdin
+        .read_to_string(&mut input)
+        .context("Failed to read stdin")?;
+    Ok(Some(input).filter(|input| !input.trim().is_empty()))
 }
 
-async fn send_message(
-    message: String,
-    log: &mut ChatSessionLog,
-    options: &Configuration,
-    tool_definitions: &[ToolDefinition],
-) -> Result<ChatMessage> {
-    let user_message = ChatMessage {
-        role: Role::User,
-        content: Some(message),
-        tool_calls: None,
+fn build_index(rebuild: bool) -> Result<()> {
+    let workspace = env::current_dir()?;
+    let ignore = IgnoreRules::load(&workspace)?;
+    let mut index = if rebuild {
+        CodeIndex::default()
+    } else {
+        CodeIndex::load(&workspace)?
     };
-    log.messages.push(user_message.clone());
-    save_log(log, &options.log_file)?;
-
-    let response = send_to_llm(
-        &log.messages,
-        &options.model,
-        &options.endpoint,
-        &options.api_key,
-        tool_definitions,
-    )
-    .await?;
-
-    if let Some(content) = &response.content {
-        let assistant_message = ChatMessage {
-            role: Role::Assistant,
-            content: Some(content.clone()),
-            tool_calls: None,
-        };
-        log.messages.push(assistant_message);
-        save_log(log, &options.log_file)?;
-    }
-
-    if let Some(tool_calls) = &response.tool_calls {
-        let tool_call_message = ChatMessage {
-            role: Role::Assistant,
-            content: None,
-            tool_calls: Some(tool_calls.clone()),
-        };
-        log.messages.push(tool_call_message);
-        save_log(log, &options.log_file)?;
-    }
-
-    Ok(response)
+    let stats = index.update(&workspace, &ignore)?;
+    index.save(&workspace)?;
+    println!(
+        "Indexed {} files in {} chunks ({} added, {} updated, {} removed, {} unchanged)",
+        index.files.len(),
+        index.chunk_count(),
+        stats.added,
+        stats.updated,
+        stats.removed,
+        stats.unchanged
+    );
+    Ok(())
 }
 
-struct Configuration {
-    model: String,
-    log_file: PathBuf,
-    api_key: String,
-    endpoint: String,
+fn config_command(matches: &ArgMatches, workspace: &Path, flags: &ConfigFlags) -> Result<()> {
+    match matches.subcommand() {
+        Some(("init", _)) => init_config(),
+        Some(("show", _)) => {
+            println!("{}", describe_config(&load_layers(workspace, flags)?)?);
+            Ok(())
+        }
+        Some(("validate", _)) => {
+            let mut config = config_from_layers(&load_layers(workspace, flags)?)?;
+            let mut problems = config.problems();
+            if problems.is_empty()
+                && let Err(e) = config.resolve_api_key()
+            {
+                problems.push(e.to_string());
+            }
+            if problems.is_empty() {
+                println!("Configuration is valid");
+                return Ok(());
+            }
+            for problem in &problems {
+                eprintln!("- {}", problem);
+            }
+            Err(Error::Config(format!(
+                "Found {} problem(s) in the configuration",
+                problems.len()
+            ))
+            .into())
+        }
+        Some(("set", sub)) => {
+            let key = sub.get_one::<String>("key").unwrap();
+            let value = sub.get_one::<String>("value").unwrap();
+            let project = sub.get_flag("project");
+            let path = if project {
+                find_project_config(workspace)?
+                    .unwrap_or_else(|| workspace.join(PROJECT_CONFIG_FILES[0]))
+            } else {
+                config_path()?
+            };
+            set_value(&path, key, value, project)?;
+            println!("Set {} in {}", key, path.display());
+            Ok(())
+        }
+        _ => unreachable!("clap requires a config subcommand"),
+    }
 }
 
-fn initialize_log(
-    system_prompt: String,
-    model: String,
-    tools: &[ToolDefinition],
-    context: Option<String>,
-) -> Result<ChatSessionLog> {
-    let mut history = Vec::new();
+/// Asks for the settings every run needs and writes them to
+/// `~/.junior.toml`.
+fn init_config() -> Result<()> {
+    let path = config_path()?;
+    if path.exists() && !ask_yes_no(&format!("{} already exists. Overwrite it?", path.display()))? {
+        return Ok(());
+    }
 
-    let system_prompt = ChatMessage {
-        role: Role::System,
-        content: Some(system_prompt),
-        tool_calls: None,
+    let endpoint = ask(
+        "Chat completions endpoint",
+        "https://api.openai.com/v1/chat/completions",
+    )?;
+    let model = ask("Model", "gpt-4o")?;
+    let api_key_cmd = ask(
+        "Command that prints your API key, e.g. `pass show openai` (leave empty to enter the key itself)",
+        "",
+    )?;
+    let api_key = if api_key_cmd.is_empty() {
+        ask("API key", "")?
+    } else {
+        String::new()
     };
-    history.push(system_prompt);
+    let history = ask("Directory for session history", "~/junior-history")?;
 
-    if let Some(ctx) = context {
-        history.push(ChatMessage {
-            role: Role::User,
-            content: Some(format!("Let's take a look at this together:\n\n{}", ctx)),
-            tool_calls: None,
-        })
+    let mut values = toml::Table::new();
+    values.insert("endpoint".into(), endpoint.into());
+    values.insert("model".into(), model.into());
+    if api_key_cmd.is_empty() {
+        values.insert("api_key".into(), api_key.into());
+    } else {
+        values.insert("api_key_cmd".into(), api_key_cmd.into());
     }
+    values.insert("history_directory_path".into(), history.into());
+    write_config_file(&path, &toml::to_string(&values)?)?;
+    println!("Wrote {}", path.display());
 
-    Ok(ChatSessionLog {
-        model,
-        tools: tools.to_vec(),
-        messages: history,
-    })
+    let config: Config = toml::Value::Table(values).try_into()?;
+    for problem in config.problems() {
+        eprintln!("Warning: {}", problem);
+    }
+    Ok(())
 }
 
-fn sanitize_path_string(path_str: &str) -> String {
-    let path = Path::new(path_str);
-
-    if path.is_absolute() {
-        path_str.to_string()
-    } else if path_str.starts_with("./") || path_str.starts_with("../") {
-        path_str.to_string()
+fn ask(question: &str, default: &str) -> Result<String> {
+    if default.is_empty() {
+        print!("{}: ", question);
     } else {
-        format!("./{}", path_str)
+        print!("{} [{}]: ", question, default);
     }
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer)? == 0 {
+        return Err(anyhow!("No answer given: stdin was closed"));
+    }
+    let answer = answer.trim();
+    Ok(if answer.is_empty() { default } else { answer }.to_string())
 }
 
-fn sanitize_and_resolve_path(path_str: &str) -> Result<PathBuf> {
-    let current_dir = env::current_dir()?;
-    let full_path = current_dir.join(path_str);
-    let canonical_cwd = current_dir.canonicalize()?;
-    let normalized = full_path.components().collect::<PathBuf>();
-    if !normalized.starts_with(&canonical_cwd) {
-        return Err(anyhow!(
-            "Unsafe path: '{}' is outside of working directory '{}'",
-            normalized.display(),
-            canonical_cwd.display()
-        ));
+/// Marks an error in the configuration or on the command line, keeping
+/// the whole chain, e.g. the file and the key that failed to parse, in
+/// its message.
+fn config_error(error: anyhow::Error) -> anyhow::Error {
+    if Error::find(&error).is_some() {
+        return error;
     }
-
-    Ok(normalized)
+    Error::Config(format!("{:#}", error)).into()
 }
 
-async fn execute_tool_call(tool_call: &ToolCall) -> Result<()> {
-    let mut args: Value = serde_json::from_str(&tool_call.function.arguments)?;
-
-    println!(
-        "[Tool Call] {} with args: {}",
-        tool_call.function.name, args
-    );
-
-    if let Some(path_str) = args.get("path").and_then(|v| v.as_str()) {
-        let path_str = sanitize_path_string(path_str);
-        let safe_path = sanitize_and_resolve_path(&path_str)?;
+fn ask_yes_no(question: &str) -> Result<bool> {
+    Ok(matches!(
+        ask(&format!("{} [y/N]", question), "")?.as_str(),
+        "y" | "Y" | "yes"
+    ))
+}
 
-        if let Some(obj) = args.as_object_mut() {
-            obj.insert(
-                "path".to_string(),
-                Value::String(safe_path.to_string_lossy().to_string()),
-            );
+#[tokio::main]
+async fn main() {
+    if let Err(e) = run().await {
+        let typed = Error::find(&e);
+        match typed {
+            Some(error) => eprintln!("error: {}", error),
+            None => eprintln!("error: {:#}", e),
         }
+        if let Some(hint) = typed.and_then(Error::hint) {
+            eprintln!("hint: {}", hint);
+        }
+        std::process::exit(typed.map_or(1, Error::exit_code));
     }
-
-    let tool = tools_registry()
-        .into_iter()
-        .find(|t| t.name() == tool_call.function.name)
-        .ok_or_else(|| anyhow!("Unknown tool function: {}", tool_call.function.name))?;
-
-    let output = tool.call(args).await?;
-    println!("[Tool Output] {}", output);
-
-    Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+async fn run() -> Result<()> {
     let matches = Command::new("junior")
         .version("0.1.0")
         .author("Hunter Horby")
         .about("A CLI interface for LLMs")
+        .args_conflicts_with_subcommands(true)
+        .subcommand(
+            Command::new("config")
+                .about("Create, inspect and edit the configuration")
+                .subcommand_required(true)
+                .subcommand(Command::new("init").about("Create ~/.junior.toml interactively"))
+                .subcommand(
+                    Command::new("show")
+                        .about("Print the effective settings and where each one comes from"),
+                )
+                .subcommand(
+                    Command::new("validate").about("Check the effective settings for problems"),
+                )
+                .subcommand(
+                    Command::new("set")
+                        .about("Set a value, e.g. `junior config set budget.on_overflow trim`")
+                        .arg(Arg::new("key").required(true))
+                        .arg(Arg::new("value").required(true))
+                        .arg(
+                            Arg::new("project")
+                                .long("project")
+                                .action(ArgAction::SetTrue)
+                                .help("Write to the project config file instead of ~/.junior.toml"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("index")
+                .about("Build or update the codebase search index in .junior/index")
+                .arg(
+                    Arg::new("rebuild")
+                        .long("rebuild")
+                        .action(ArgAction::SetTrue)
+                        .help("Discard the existing index and index every file again"),
+                ),
+        )
         .arg(
             Arg::new("prompt")
-                .help("The prompt to send to the LLM")
+                .help("The prompt to send to the LLM, or /<command> to run a saved prompt. Read from stdin when omitted")
                 .index(1),
         )
+        .arg(
+            Arg::new("prompt_file")
+                .long("prompt-file")
+                .value_name("FILE")
+                .conflicts_with("prompt")
+                .help("Read the prompt from a file"),
+        )
+        .arg(
+            Arg::new("args")
+                .help("Arguments for a /<command>")
+                .index(2)
+                .num_args(0..),
+        )
+        .arg(
+            Arg::new("var")
+                .long("var")
+                .value_name("KEY=VALUE")
+                .action(ArgAction::Append)
+                .help("Value for a {{KEY}} placeholder in a /<command> (repeatable)"),
+        )
         .arg(
             Arg::new("file")
                 .long("file")
                 .short('f')
+                .value_name("PATH")
+                .action(ArgAction::Append)
+                .help("File, directory or glob to attach to the prompt (repeatable)"),
+        )
+        .arg(
+            Arg::new("model")
+                .long("model")
+                .value_name("MODEL")
+                .help("Model to use, overriding the config and any /<command> pin"),
+        )
+        .arg(
+            Arg::new("endpoint")
+                .long("endpoint")
+                .value_name("URL")
+                .help("Chat completions endpoint to send requests to"),
+        )
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .value_name("NAME")
+                .help("Config profile to use (default: $JUNIOR_PROFILE, then default_profile)"),
+        )
+        .arg(
+            Arg::new("image")
+                .long("image")
+                .value_name("PATH")
+                .action(ArgAction::Append)
+                .help("PNG, JPEG or WebP image to attach to the prompt (repeatable)"),
+        )
+        .arg(
+            Arg::new("system")
+                .long("system")
                 .value_name("FILE")
-                .help("Path to a file whose contents will be appended to the prompt"),
+                .help("Use this file as the system prompt template"),
+        )
+        .arg(
+            Arg::new("tools")
+                .long("tools")
+                .value_name("SET")
+                .help("Tool set to offer the model, e.g. `readonly` or `full`"),
+        )
+        .arg(
+            Arg::new("disable")
+                .long("disable")
+                .value_name("TOOL")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .help("Tool to withhold from the model (repeatable)"),
+        )
+        .arg(
+            Arg::new("no_tools")
+                .long("no-tools")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["tools", "disable"])
+                .help("Send a plain chat request without offering any tools"),
+        )
+        .arg(
+            Arg::new("fail_on_tool_error")
+                .long("fail-on-tool-error")
+                .action(ArgAction::SetTrue)
+                .help("Stop at the first failed tool call instead of telling the model about it"),
+        )
+        .arg(
+            Arg::new("temperature")
+                .long("temperature")
+                .value_name("N")
+                .value_parser(clap::value_parser!(f64))
+                .help("Sampling temperature"),
+        )
+        .arg(
+            Arg::new("top_p")
+                .long("top-p")
+                .value_name("N")
+                .value_parser(clap::value_parser!(f64))
+                .help("Nucleus sampling probability mass"),
+        )
+        .arg(
+            Arg::new("max_tokens")
+                .long("max-tokens")
+                .value_name("N")
+                .value_parser(clap::value_parser!(u32))
+                .help("Most tokens the model may generate per response"),
+        )
+        .arg(
+            Arg::new("stop")
+                .long("stop")
+                .value_name("TEXT")
+                .action(ArgAction::Append)
+                .help("Sequence at which the model stops generating (repeatable)"),
+        )
+        .arg(
+            Arg::new("seed")
+                .long("seed")
+                .value_name("N")
+                .value_parser(clap::value_parser!(i64))
+                .help("Seed for providers that support reproducible sampling"),
+        )
+        .arg(
+            Arg::new("parallel_tool_calls")
+                .long("parallel-tool-calls")
+                .value_name("BOOL")
+                .value_parser(clap::value_parser!(bool))
+                .help("Whether the model may request several tool calls at once"),
+        )
+        .arg(
+            Arg::new("tool_choice")
+                .long("tool-choice")
+                .value_name("CHOICE")
+                .help("none, auto, required, or the name of a tool the model must call"),
+        )
+        .arg(
+            Arg::new("extra")
+                .long("extra")
+                .value_name("KEY=JSON")
+                .action(ArgAction::Append)
+                .help("Extra field to add to the request body (repeatable)"),
+        )
+        .arg(
+            Arg::new("repo_map")
+                .long("repo-map")
+                .action(ArgAction::SetTrue)
+                .help("Attach an outline of the project's files and top-level symbols"),
         )
         .get_matches();
 
-    let config = load_config()?;
+    let workspace = env::current_dir()?;
+    let flags = ConfigFlags {
+        profile: matches.get_one::<String>("profile").cloned(),
+        model: matches.get_one::<String>("model").cloned(),
+        endpoint: matches.get_one::<String>("endpoint").cloned(),
+        temperature: matches.get_one::<f64>("temperature").copied(),
+        top_p: matches.get_one::<f64>("top_p").copied(),
+        max_tokens: matches.get_one::<u32>("max_tokens").copied(),
+        stop: matches
+            .get_many::<String>("stop")
+            .unwrap_or_default()
+            .cloned()
+            .collect(),
+        seed: matches.get_one::<i64>("seed").copied(),
+        parallel_tool_calls: matches.get_one::<bool>("parallel_tool_calls").copied(),
+        tool_choice: matches.get_one::<String>("tool_choice").cloned(),
+        extra: parse_extra(&matches).map_err(config_error)?,
+    };
+    match matches.subcommand() {
+        Some(("index", sub)) => return build_index(sub.get_flag("rebuild")),
+        Some(("config", sub)) => {
+            return config_command(sub, &workspace, &flags).map_err(config_error);
+        }
+        _ => {}
+    }
+    let config = load_config(&workspace, &flags).map_err(config_error)?;
+    let log_file = create_session_file(&config.history_path()?)?;
+
+    let mut prompt = match matches.get_one::<String>("prompt_file") {
+        Some(path) => Some(
+            fs::read_to_string(path)
+                .with_context(|| format!("Failed to read prompt file {}", path))
+                .map_err(config_error)?,
+        ),
+        None => matches.get_one::<String>("prompt").cloned(),
+    };
+    let args: Vec<String> = matches
+        .get_many::<String>("args")
+        .unwrap_or_default()
+        .cloned()
+        .collect();
+    let file_specs: Vec<String> = matches
+        .get_many::<String>("file")
+        .unwrap_or_default()
+        .cloned()
+        .collect();
+    let images = matches
+        .get_many::<String>("image")
+        .unwrap_or_default()
+        .map(|path| {
+            let image = load_image(&workspace.join(path), config.context.max_image_bytes)?;
+            Ok((path.clone(), image))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut piped = read_piped_stdin()?;
+    // Piped text, such as a diff, is full of `@`s that are not mentions.
+    let from_stdin = prompt.is_none() && piped.is_some();
+    if prompt.is_none() {
+        prompt = piped.take();
+    }
+    let mut prompt = prompt
+        .map(|p| p.trim().to_string())
+        .filter(|p| !p.is_empty())
+        .ok_or_else(|| {
+            config_error(anyhow!(
+                "No prompt given: pass one as an argument, with --prompt-file, or on stdin"
+            ))
+        })?;
+
+    let mentioned: Vec<String> = std::iter::once(&prompt)
+        .filter(|_| !from_stdin)
+        .chain(&args)
+        .flat_map(|text| mentions(text))
+        .collect();
+    let ignore = Arc::new(IgnoreRules::load(&workspace)?);
+    // Paths that do not resolve are command line mistakes; paths outside
+    // the workspace keep their sandbox error.
+    let mut context = gather(&workspace, &file_specs, &mentioned, config.context, &ignore)
+        .map_err(config_error)?;
+    if let Some(input) = piped {
+        context.push(ContextSource::Stdin, input, config.context);
+    }
 
-    let prompt = matches
+    let mut model = config.model.clone();
+    let mut tool_set = matches.get_one::<String>("tools").cloned();
+    let command_name = matches
         .get_one::<String>("prompt")
-        .map(|s| s.as_str())
-        .unwrap_or("");
-    let system_prompt = include_str!("system_prompt.md").to_string();
-    let tool_definitions = tool_definitions();
+        .and_then(|p| p.strip_prefix('/'));
+    if let Some(name) = command_name {
+        let command = find_command(&workspace, name).map_err(config_error)?;
+        let vars = parse_vars(&matches).map_err(config_error)?;
+        if let Some(pinned) = &command.model
+            && flags.model.is_none()
+        {
+            model = pinned.clone();
+        }
+        if tool_set.is_none() {
+            tool_set = command.tool_set.clone();
+        }
+        prompt = command
+            .render(&args, &context.paths(), &vars)
+            .map_err(config_error)?;
+    } else if !args.is_empty() {
+        return Err(config_error(anyhow!(
+            "Unexpected arguments {:?}: quote the prompt, or use a /<command> to pass arguments",
+            args
+        )));
+    }
 
-    let mut additional_context: Option<String> = None;
-    if let Some(file_path) = matches.get_one::<String>("file") {
-        let mut contents = String::new();
-        File::open(file_path)?.read_to_string(&mut contents)?;
-        additional_context = Some(contents);
+    if matches.get_flag("repo_map") {
+        let map = repo_map::build(&workspace, &ignore, &prompt, config.context.repo_map_tokens)?;
+        context.push(ContextSource::RepoMap, map, config.context);
     }
-    let mut log = initialize_log(
-        system_prompt.clone(),
-        config.model.clone(),
-        &tool_definitions,
-        additional_context,
-    )
-    .unwrap();
 
-    let response = send_message(prompt.to_string(), &mut log, &config, &tool_definitions).await?;
+    let disabled: Vec<String> = matches
+        .get_many::<String>("disable")
+        .unwrap_or_default()
+        .cloned()
+        .collect();
+    let tools = if matches.get_flag("no_tools") {
+        ToolRegistry::new()
+    } else {
+        config
+            .tool_registry(tool_set.as_deref(), &disabled)
+            .map_err(config_error)?
+    };
 
-    if let Some(content) = &response.content {
-        println!("{}", content);
+    let mut agent = Agent::new(model, &config.endpoint, &config.api_key);
+    agent.backend = config.backend().map_err(config_error)?;
+    agent.fallbacks = load_fallbacks(&workspace, &flags, &config).map_err(config_error)?;
+    agent.generation = config.generation.clone();
+    agent.budget = config.budget.for_model(&agent.model);
+    agent.compaction = config.compaction.enabled.then(|| config.compaction.clone());
+    agent.workspace = workspace;
+    agent.ignore = ignore;
+    agent.tools = tools;
+    agent.hooks = config.hooks.clone();
+    let system_prompt = match matches.get_one::<String>("system") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => config.system_prompt_path()?,
+    };
+    agent.system_prompt = load_system_prompt(&agent.workspace, system_prompt.as_deref())?;
+    agent.on_event = Some(Box::new(print_event));
+    agent.approve = Some(Box::new(approve_tool_call));
+    let cancel = Arc::new(CancelToken::default());
+    handle_ctrl_c(Arc::clone(&cancel));
+    agent.cancel = Some(cancel);
+    agent.stop_on_tool_error = matches.get_flag("fail_on_tool_error");
+
+    let mut session = agent.new_session().with_log_file(log_file);
+    if !context.is_empty() || !images.is_empty() {
+        let mut parts = Vec::new();
+        if !context.is_empty() {
+            parts.push(ContentPart::text(format!(
+                "Let's take a look at this together:\n\n{}",
+                context.render()
+            )));
+        }
+        for (path, image) in images {
+            parts.push(ContentPart::text(format!("Image: {}", path)));
+            parts.push(image);
+        }
+        session.push(match parts.as_slice() {
+            [ContentPart::Text { text }] => ChatMessage::user(text.clone()),
+            _ => ChatMessage::user_parts(parts),
+        })?;
     }
 
-    if let Some(tool_calls) = &response.tool_calls {
-        for tool_call in tool_calls {
-            if let Err(e) = execute_tool_call(tool_call).await {
-                eprintln!("❌ Error executing tool call: {}", e);
-                break;
+    if let Err(e) = agent.run(&mut session, prompt).await {
+        if let Some(Error::Cancelled) = Error::find(&e) {
+            if let Some(path) = session.log_file() {
+                eprintln!("Cancelled; the session was saved to {}", path.display());
             }
+            std::process::exit(130);
         }
+        return Err(e);
     }
 
     Ok(())
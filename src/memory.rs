@@ -0,0 +1,90 @@
+use anyhow::{Context, Result, anyhow};
+use serde::Deserialize;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::prompt::user_junior_dir;
+
+/// Memory file read from the workspace root and from `~/.junior`.
+pub const MEMORY_FILE: &str = "JUNIOR.md";
+
+/// Memory files larger than this are cut short in the system prompt.
+const MAX_MEMORY_BYTES: usize = 32 * 1024;
+
+/// Which memory file a fact belongs in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MemoryScope {
+    /// `JUNIOR.md` in the workspace, shared through version control.
+    #[default]
+    Project,
+    /// `~/.junior/JUNIOR.md`, for the user's own preferences.
+    User,
+}
+
+pub fn memory_path(workspace: &Path, scope: MemoryScope) -> Option<PathBuf> {
+    match scope {
+        MemoryScope::Project => Some(workspace.join(MEMORY_FILE)),
+        MemoryScope::User => user_junior_dir().map(|dir| dir.join(MEMORY_FILE)),
+    }
+}
+
+/// The user and project memory files as a section for the end of the
+/// system prompt, or an empty string when neither exists.
+pub fn load_memory(workspace: &Path) -> String {
+    let sections: Vec<String> = [
+        (MemoryScope::User, "User memory (~/.junior/JUNIOR.md)"),
+        (MemoryScope::Project, "Project memory (JUNIOR.md)"),
+    ]
+    .into_iter()
+    .filter_map(|(scope, title)| {
+        let mut text = fs::read_to_string(memory_path(workspace, scope)?).ok()?;
+        if text.trim().is_empty() {
+            return None;
+        }
+        if text.len() > MAX_MEMORY_BYTES {
+            let mut end = MAX_MEMORY_BYTES;
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            text.truncate(end);
+            text.push_str("\n(truncated)");
+        }
+        Some(format!("## {}\n\n{}", title, text.trim_end()))
+    })
+    .collect();
+
+    if sections.is_empty() {
+        return String::new();
+    }
+    format!(
+        "# Memory\n\nNotes kept from earlier sessions. Follow them unless the user says otherwise.\n\n{}",
+        sections.join("\n\n")
+    )
+}
+
+/// Appends `fact` to a memory file as a list item, creating the file if
+/// needed.
+pub fn remember(path: &Path, fact: &str) -> Result<()> {
+    let fact = fact.split_whitespace().collect::<Vec<_>>().join(" ");
+    if fact.is_empty() {
+        return Err(anyhow!("Nothing to remember: the fact is empty"));
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let existing = fs::read_to_string(path).unwrap_or_default();
+    let separator = if existing.is_empty() || existing.ends_with('\n') {
+        ""
+    } else {
+        "\n"
+    };
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    writeln!(file, "{}- {}", separator, fact)?;
+    Ok(())
+}
@@ -0,0 +1,197 @@
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+    Tool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub tool_type: String,
+    pub function: FunctionCall,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FunctionCall {
+    pub name: String,
+    pub arguments: String,
+}
+
+/// Message content: plain text, or a list of typed parts for messages that
+/// mix text and images.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum Content {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ImageUrl {
+    /// An `https:` URL or a base64 `data:` URL.
+    pub url: String,
+}
+
+impl Content {
+    /// The text of the content, with the text parts joined by newlines and
+    /// images left out.
+    pub fn as_text(&self) -> Cow<'_, str> {
+        match self {
+            Content::Text(text) => Cow::Borrowed(text),
+            Content::Parts(parts) => Cow::Owned(
+                parts
+                    .iter()
+                    .filter_map(|part| match part {
+                        ContentPart::Text { text } => Some(text.as_str()),
+                        ContentPart::ImageUrl { .. } => None,
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ),
+        }
+    }
+
+    pub fn image_count(&self) -> usize {
+        match self {
+            Content::Text(_) => 0,
+            Content::Parts(parts) => parts
+                .iter()
+                .filter(|part| matches!(part, ContentPart::ImageUrl { .. }))
+                .count(),
+        }
+    }
+}
+
+impl From<String> for Content {
+    fn from(text: String) -> Self {
+        Content::Text(text)
+    }
+}
+
+impl ContentPart {
+    pub fn text(text: impl Into<String>) -> Self {
+        ContentPart::Text { text: text.into() }
+    }
+
+    pub fn image(url: impl Into<String>) -> Self {
+        ContentPart::ImageUrl {
+            image_url: ImageUrl { url: url.into() },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChatMessage {
+    pub role: Role,
+    pub content: Option<Content>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+impl ChatMessage {
+    pub fn system(content: impl Into<String>) -> Self {
+        Self::text(Role::System, content)
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self::text(Role::User, content)
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self::text(Role::Assistant, content)
+    }
+
+    /// A user message made of text and image parts.
+    pub fn user_parts(parts: Vec<ContentPart>) -> Self {
+        ChatMessage {
+            role: Role::User,
+            content: Some(Content::Parts(parts)),
+            tool_calls: None,
+            tool_call_id: None,
+        }
+    }
+
+    /// The message's text, without any images.
+    pub fn text_content(&self) -> Option<Cow<'_, str>> {
+        self.content.as_ref().map(Content::as_text)
+    }
+
+    /// The result of a tool call, sent back to the model on the next turn.
+    pub fn tool_result(tool_call_id: impl Into<String>, content: impl Into<String>) -> Self {
+        ChatMessage {
+            role: Role::Tool,
+            content: Some(Content::Text(content.into())),
+            tool_calls: None,
+            tool_call_id: Some(tool_call_id.into()),
+        }
+    }
+
+    fn text(role: Role, content: impl Into<String>) -> Self {
+        ChatMessage {
+            role,
+            content: Some(Content::Text(content.into())),
+            tool_calls: None,
+            tool_call_id: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn text_content_is_a_plain_string() {
+        let message = ChatMessage::user("hi");
+
+        let value = serde_json::to_value(&message).unwrap();
+
+        assert_eq!(value, json!({ "role": "user", "content": "hi" }));
+        let back: ChatMessage = serde_json::from_value(value).unwrap();
+        assert!(matches!(back.content, Some(Content::Text(ref text)) if text == "hi"));
+    }
+
+    #[test]
+    fn mixed_content_round_trips_as_typed_parts() {
+        let message = ChatMessage::user_parts(vec![
+            ContentPart::text("What is this?"),
+            ContentPart::image("data:image/png;base64,AAAA"),
+            ContentPart::text("Be brief."),
+        ]);
+
+        let value = serde_json::to_value(&message).unwrap();
+
+        assert_eq!(
+            value,
+            json!({
+                "role": "user",
+                "content": [
+                    { "type": "text", "text": "What is this?" },
+                    { "type": "image_url", "image_url": { "url": "data:image/png;base64,AAAA" } },
+                    { "type": "text", "text": "Be brief." },
+                ],
+            })
+        );
+        let back: ChatMessage = serde_json::from_value(value).unwrap();
+        let content = back.content.unwrap();
+        assert_eq!(content.as_text(), "What is this?\nBe brief.");
+        assert_eq!(content.image_count(), 1);
+    }
+}
@@ -0,0 +1,228 @@
+use anyhow::{Context, Result};
+use regex::{Captures, Regex};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::agent::DEFAULT_SYSTEM_PROMPT;
+use crate::ignore_rules::IgnoreRules;
+use crate::tools::ToolRegistry;
+
+/// Directory, both in a project and in the home directory, that holds
+/// junior's prompt files.
+pub const JUNIOR_DIR: &str = ".junior";
+
+const SYSTEM_PROMPT_FILE: &str = "system_prompt.md";
+
+/// Matches a `{{name}}` template placeholder, capturing the name.
+pub(crate) const PLACEHOLDER_PATTERN: &str = r"\{\{\s*([A-Za-z0-9_.-]+)\s*\}\}";
+
+pub fn user_junior_dir() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(JUNIOR_DIR))
+}
+
+/// Picks the system prompt template for a run. An explicit override file
+/// wins, then `.junior/system_prompt.md` in the workspace, then
+/// `~/.junior/system_prompt.md`, then the built-in prompt.
+pub fn load_system_prompt(workspace: &Path, override_path: Option<&Path>) -> Result<String> {
+    let dirs = [Some(workspace.join(JUNIOR_DIR)), user_junior_dir()];
+    first_system_prompt(override_path, dirs.into_iter().flatten())
+}
+
+/// The override file if any, else the prompt file in the first of `dirs`
+/// that has one, else the built-in prompt.
+fn first_system_prompt(
+    override_path: Option<&Path>,
+    dirs: impl IntoIterator<Item = PathBuf>,
+) -> Result<String> {
+    if let Some(path) = override_path {
+        return fs::read_to_string(path)
+            .with_context(|| format!("Failed to read system prompt {}", path.display()));
+    }
+
+    for dir in dirs {
+        let path = dir.join(SYSTEM_PROMPT_FILE);
+        if path.is_file() {
+            return fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read system prompt {}", path.display()));
+        }
+    }
+
+    Ok(DEFAULT_SYSTEM_PROMPT.to_string())
+}
+
+/// Replaces `{{name}}` placeholders with their values. Unknown names are
+/// left untouched so literal braces in a prompt survive.
+pub fn render(template: &str, vars: &HashMap<String, String>) -> String {
+    let placeholder = Regex::new(PLACEHOLDER_PATTERN).unwrap();
+    placeholder
+        .replace_all(template, |caps: &Captures| {
+            vars.get(&caps[1])
+                .cloned()
+                .unwrap_or_else(|| caps[0].to_string())
+        })
+        .into_owned()
+}
+
+/// Values available to system prompt templates: `cwd`, `os`, `date`,
+/// `git_branch`, `tools` and `files`.
+pub fn template_variables(
+    workspace: &Path,
+    tools: &ToolRegistry,
+    ignore: &IgnoreRules,
+) -> HashMap<String, String> {
+    HashMap::from([
+        ("cwd".to_string(), workspace.display().to_string()),
+        ("os".to_string(), env::consts::OS.to_string()),
+        ("date".to_string(), today()),
+        (
+            "git_branch".to_string(),
+            git_branch(workspace).unwrap_or_else(|| "(none)".to_string()),
+        ),
+        ("tools".to_string(), tools.names().join(", ")),
+        ("files".to_string(), top_level_listing(workspace, ignore)),
+    ])
+}
+
+/// Today's UTC date as `YYYY-MM-DD`.
+fn today() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Converts days since the Unix epoch to a proleptic Gregorian date.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// The checked-out branch of the repository containing `workspace`, read
+/// straight from `.git/HEAD`.
+fn git_branch(workspace: &Path) -> Option<String> {
+    let git_dir = workspace
+        .ancestors()
+        .map(|dir| dir.join(".git"))
+        .find(|dir| dir.is_dir())?;
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    match head.trim().strip_prefix("ref: refs/heads/") {
+        Some(branch) => Some(branch.to_string()),
+        None => Some(format!("detached at {}", head.trim().get(..7)?)),
+    }
+}
+
+/// Names of the entries directly inside `workspace` that are not ignored,
+/// directories marked with a trailing slash.
+fn top_level_listing(workspace: &Path, ignore: &IgnoreRules) -> String {
+    let Ok(entries) = fs::read_dir(workspace) else {
+        return String::new();
+    };
+    let mut names: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            let is_dir = path.is_dir();
+            if ignore.is_ignored(&path, is_dir) {
+                return None;
+            }
+            let name = entry.file_name().to_string_lossy().to_string();
+            Some(if is_dir { format!("{}/", name) } else { name })
+        })
+        .collect();
+    names.sort();
+    names.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn renders_known_placeholders_and_keeps_the_rest() {
+        let vars = vars(&[("os", "linux"), ("git_branch", "main"), ("a.b-c", "x")]);
+
+        assert_eq!(
+            render("On {{os}} ({{ git_branch }}), {{a.b-c}}.", &vars),
+            "On linux (main), x."
+        );
+        assert_eq!(
+            render("{{unknown}} {{ os}} {{os name}} {os} {{}}", &vars),
+            "{{unknown}} linux {{os name}} {os} {{}}"
+        );
+        // Values are inserted as they are, not rendered again.
+        assert_eq!(
+            render(
+                "{{1}}",
+                &HashMap::from([("1".to_string(), "{{os}}".to_string())])
+            ),
+            "{{os}}"
+        );
+    }
+
+    #[test]
+    fn converts_days_to_dates_around_leap_years() {
+        for (days, date) in [
+            (0, (1970, 1, 1)),
+            (-1, (1969, 12, 31)),
+            (10_956, (1999, 12, 31)),
+            (11_016, (2000, 2, 29)),
+            (11_017, (2000, 3, 1)),
+            (19_416, (2023, 2, 28)),
+            (19_417, (2023, 3, 1)),
+            (19_782, (2024, 2, 29)),
+            (47_540, (2100, 2, 28)),
+            (47_541, (2100, 3, 1)),
+        ] {
+            assert_eq!(civil_from_days(days), date, "day {}", days);
+        }
+    }
+
+    #[test]
+    fn the_override_file_wins_then_the_first_directory_with_a_prompt() {
+        let root = tempfile::tempdir().unwrap();
+        let project = root.path().join("project");
+        let user = root.path().join("user");
+        let empty = root.path().join("empty");
+        for (dir, text) in [(&project, "project prompt"), (&user, "user prompt")] {
+            fs::create_dir(dir).unwrap();
+            fs::write(dir.join(SYSTEM_PROMPT_FILE), text).unwrap();
+        }
+        let flag = root.path().join("flag.md");
+        fs::write(&flag, "flag prompt").unwrap();
+        let load = |flag: Option<&Path>, dirs: &[&PathBuf]| {
+            first_system_prompt(flag, dirs.iter().map(|dir| dir.to_path_buf())).unwrap()
+        };
+
+        assert_eq!(load(Some(&flag), &[&project, &user]), "flag prompt");
+        assert_eq!(load(None, &[&project, &user]), "project prompt");
+        assert_eq!(load(None, &[&empty, &user]), "user prompt");
+        assert_eq!(load(None, &[&empty]), DEFAULT_SYSTEM_PROMPT);
+
+        let error = first_system_prompt(Some(&root.path().join("missing.md")), []).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("Failed to read system prompt ")
+        );
+    }
+}
@@ -0,0 +1,330 @@
+use anyhow::Result;
+use regex::Regex;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use crate::ignore_rules::IgnoreRules;
+use crate::tokens::estimate_text;
+
+/// Outline size used when no budget is given.
+pub const DEFAULT_MAX_TOKENS: usize = 2_048;
+
+/// Files larger than this are listed without an outline.
+const MAX_PARSE_BYTES: u64 = 512 * 1024;
+
+/// The directory listing gets at most `1 / DIRECTORY_SHARE` of the budget.
+const DIRECTORY_SHARE: usize = 4;
+
+/// Longest signature kept for a single item.
+const MAX_ITEM_CHARS: usize = 100;
+
+const STOPWORDS: &[&str] = &[
+    "the", "and", "for", "this", "that", "with", "from", "into", "where", "what", "should",
+    "would", "could", "does", "how", "why", "are", "add", "new", "use", "can", "put", "all",
+    "there", "which",
+];
+
+struct FileOutline {
+    path: String,
+    items: Vec<String>,
+    score: usize,
+}
+
+/// Builds a compact outline of the tree under `root`: the directories with
+/// their file counts, then each file with its top-level items. Files are
+/// ranked by how well their path and items match `query`, and the outline
+/// is cut to roughly `max_tokens`, dropping items before dropping files.
+/// The directory listing takes at most a quarter of that budget.
+pub fn build(root: &Path, ignore: &IgnoreRules, query: &str, max_tokens: usize) -> Result<String> {
+    let root = root.canonicalize()?;
+    let terms = query_terms(query);
+
+    let mut directories: BTreeMap<String, usize> = BTreeMap::new();
+    let mut files = Vec::new();
+    for path in ignore.walk(&root)? {
+        let relative = path.strip_prefix(&root).unwrap_or(&path);
+        let dir = relative
+            .parent()
+            .map(|p| p.to_string_lossy().to_string())
+            .filter(|p| !p.is_empty())
+            .map_or_else(|| "./".to_string(), |p| format!("{}/", p));
+        *directories.entry(dir).or_default() += 1;
+
+        let relative = relative.to_string_lossy().to_string();
+        let items = outline(&path).unwrap_or_default();
+        let score = score(&relative, &items, &terms);
+        files.push(FileOutline {
+            path: relative,
+            items,
+            score,
+        });
+    }
+    files.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.path.cmp(&b.path)));
+
+    let mut map = String::from("Directories:\n");
+    for line in directory_lines(&directories, max_tokens / DIRECTORY_SHARE) {
+        map.push_str(&line);
+    }
+    map.push_str("\nFiles:\n");
+
+    // Keep room for the line saying how many files were left out.
+    let max_tokens = max_tokens.saturating_sub(estimate_text(&more_files(files.len())));
+    let mut used = estimate_text(&map);
+    let mut shown = 0;
+    for file in &files {
+        let path_line = format!("{}\n", file.path);
+        let path_cost = estimate_text(&path_line);
+        if used + path_cost > max_tokens {
+            break;
+        }
+        map.push_str(&path_line);
+        used += path_cost;
+        shown += 1;
+
+        // Leave room for the remaining paths before spending on items.
+        for item in &file.items {
+            let line = format!("  {}\n", item);
+            let cost = estimate_text(&line);
+            if used + cost > max_tokens * 3 / 4 {
+                break;
+            }
+            map.push_str(&line);
+            used += cost;
+        }
+    }
+    if shown < files.len() {
+        map.push_str(&more_files(files.len() - shown));
+    }
+
+    Ok(map)
+}
+
+fn more_files(count: usize) -> String {
+    format!("({} more files not shown)\n", count)
+}
+
+/// Lists directories with their file counts in about `max_tokens`. When
+/// the full listing is too long, deeper directories are folded into their
+/// ancestors; if even the top level does not fit, the list is cut short.
+fn directory_lines(directories: &BTreeMap<String, usize>, max_tokens: usize) -> Vec<String> {
+    let depth_of = |dir: &str| dir.matches('/').count();
+    let deepest = directories
+        .keys()
+        .map(|dir| depth_of(dir))
+        .max()
+        .unwrap_or(0);
+
+    let mut lines = Vec::new();
+    for depth in (1..=deepest).rev() {
+        let mut folded: BTreeMap<String, (usize, bool)> = BTreeMap::new();
+        for (dir, count) in directories {
+            let ancestor: String = dir.split_inclusive('/').take(depth).collect();
+            let entry = folded.entry(ancestor).or_default();
+            entry.0 += count;
+            entry.1 |= depth_of(dir) > depth;
+        }
+        lines = folded
+            .iter()
+            .map(|(dir, (count, has_folded))| {
+                if *has_folded {
+                    format!("  {} ({} files, subdirectories included)\n", dir, count)
+                } else {
+                    format!("  {} ({} files)\n", dir, count)
+                }
+            })
+            .collect();
+        if lines.iter().map(|line| estimate_text(line)).sum::<usize>() <= max_tokens {
+            return lines;
+        }
+    }
+
+    let mut used = 0;
+    let mut kept = Vec::new();
+    let total = lines.len();
+    for line in lines {
+        let remaining = format!("  … {} more\n", total - kept.len());
+        let cost = estimate_text(&line);
+        if used + cost + estimate_text(&remaining) > max_tokens {
+            kept.push(remaining);
+            break;
+        }
+        used += cost;
+        kept.push(line);
+    }
+    kept
+}
+
+/// The top-level items of a source file, or `None` for files in languages
+/// without a parser.
+pub fn outline(path: &Path) -> Option<Vec<String>> {
+    let pattern = item_pattern(path.extension()?.to_str()?)?;
+    if fs::metadata(path).ok()?.len() > MAX_PARSE_BYTES {
+        return None;
+    }
+    let text = fs::read_to_string(path).ok()?;
+    let regex = Regex::new(pattern).unwrap();
+    Some(
+        text.lines()
+            .filter(|line| regex.is_match(line))
+            .map(signature)
+            .collect(),
+    )
+}
+
+/// Matches the first line of a top-level item. Items must start at the
+/// beginning of the line, which keeps nested methods and locals out.
+fn item_pattern(extension: &str) -> Option<&'static str> {
+    Some(match extension {
+        "rs" => {
+            r"^(pub(\([\w:]+\))?\s+)?(async\s+)?(unsafe\s+)?(extern\s+\S+\s+)?(fn|struct|enum|trait|type|mod|const|static|impl|union|macro_rules!)[\s<!]"
+        }
+        "py" => r"^(async\s+)?(def|class)\s+\w+",
+        "js" | "mjs" | "cjs" | "jsx" | "ts" | "tsx" => {
+            r"^(export\s+)?(default\s+)?(declare\s+)?(abstract\s+)?(async\s+)?(function\*?|class|interface|type|enum|const|let|namespace)\s+\w+"
+        }
+        "go" => r"^(func|type)\s+",
+        "java" | "kt" | "cs" | "swift" | "scala" => {
+            r"^((public|private|protected|internal|abstract|final|static|sealed|data|open|partial)\s+)*(class|interface|enum|record|object|struct|fun|func|protocol|extension|trait)\s+\w+"
+        }
+        "rb" => r"^(class|module|def)\s+",
+        "php" => r"^((abstract|final)\s+)?(class|interface|trait|enum|function)\s+\w+",
+        "c" | "h" | "cc" | "cpp" | "hpp" => {
+            r"^((struct|class|enum|union|namespace|typedef)\b|[A-Za-z_][\w\s\*&:<>,]*[\s\*&]\**~?[A-Za-z_][\w:]*\s*\([^;]*$)"
+        }
+        _ => return None,
+    })
+}
+
+fn signature(line: &str) -> String {
+    let line = line.trim_end();
+    let line = line
+        .strip_suffix('{')
+        .or_else(|| line.strip_suffix(':'))
+        .or_else(|| line.strip_suffix('('))
+        .unwrap_or(line)
+        .trim_end();
+    if line.chars().count() > MAX_ITEM_CHARS {
+        let cut: String = line.chars().take(MAX_ITEM_CHARS).collect();
+        format!("{}…", cut)
+    } else {
+        line.to_string()
+    }
+}
+
+fn query_terms(query: &str) -> HashSet<String> {
+    query
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .map(str::to_lowercase)
+        .filter(|term| term.len() >= 3 && !STOPWORDS.contains(&term.as_str()))
+        .collect()
+}
+
+/// Path matches weigh more than item matches; shallower files win ties.
+fn score(path: &str, items: &[String], terms: &HashSet<String>) -> usize {
+    let path_lower = path.to_lowercase();
+    let mut score = 0;
+    for term in terms {
+        if path_lower.contains(term.as_str()) {
+            score += 10;
+        }
+        score += 2 * items
+            .iter()
+            .filter(|item| item.to_lowercase().contains(term.as_str()))
+            .count()
+            .min(5);
+    }
+    let depth = path.matches('/').count();
+    score * 4 + 3usize.saturating_sub(depth)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(path: &Path, text: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, text).unwrap();
+    }
+
+    #[test]
+    fn outlines_top_level_items_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lib.rs");
+        write(
+            &path,
+            "use std::fs;\n\npub struct Config {\n    name: String,\n}\n\nimpl Config {\n    pub fn load() -> Self {\n        todo!()\n    }\n}\n\npub(crate) async fn run(args: &[String]) {\n}\n",
+        );
+        let python = dir.path().join("tool.py");
+        write(
+            &python,
+            "import os\n\nclass Tool:\n    def call(self):\n        pass\n\ndef main():\n    pass\n",
+        );
+
+        assert_eq!(
+            outline(&path).unwrap(),
+            [
+                "pub struct Config",
+                "impl Config",
+                "pub(crate) async fn run(args: &[String])"
+            ]
+        );
+        assert_eq!(outline(&python).unwrap(), ["class Tool", "def main()"]);
+        write(&dir.path().join("notes.txt"), "fn not_code() {}\n");
+        assert_eq!(outline(&dir.path().join("notes.txt")), None);
+    }
+
+    #[test]
+    fn scores_path_matches_above_item_matches() {
+        let terms = query_terms("Where should the config loader go?");
+        assert_eq!(
+            terms,
+            HashSet::from(["config".to_string(), "loader".to_string()])
+        );
+
+        let by_path = score("src/config.rs", &[], &terms);
+        let by_item = score("src/agent.rs", &["fn load_config()".to_string()], &terms);
+        let unrelated = score("src/llm.rs", &["fn send()".to_string()], &terms);
+        assert!(by_path > by_item, "{} <= {}", by_path, by_item);
+        assert!(by_item > unrelated, "{} <= {}", by_item, unrelated);
+        assert!(score("config.rs", &[], &terms) > by_path);
+    }
+
+    #[test]
+    fn keeps_a_large_tree_within_the_budget() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..300 {
+            write(
+                &dir.path().join(format!("packages/pkg{}/src/lib.rs", i)),
+                "pub fn run() {}\n",
+            );
+        }
+        let ignore = IgnoreRules::load(dir.path()).unwrap();
+
+        let map = build(dir.path(), &ignore, "", 200).unwrap();
+
+        assert!(estimate_text(&map) <= 200, "{}", map);
+        assert!(
+            map.contains("  packages/ (300 files, subdirectories included)\n"),
+            "{}",
+            map
+        );
+        assert!(map.contains("more files not shown)\n"), "{}", map);
+    }
+
+    #[test]
+    fn cuts_the_directory_list_when_folding_is_not_enough() {
+        let directories: BTreeMap<String, usize> =
+            (0..100).map(|i| (format!("package{}/", i), 1)).collect();
+
+        let lines = directory_lines(&directories, 50);
+
+        assert!(lines.len() < 100);
+        assert_eq!(lines[0], "  package0/ (1 files)\n");
+        assert_eq!(
+            lines.last().unwrap(),
+            &format!("  … {} more\n", 100 - (lines.len() - 1))
+        );
+        assert!(lines.iter().map(|line| estimate_text(line)).sum::<usize>() <= 50);
+    }
+}
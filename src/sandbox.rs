@@ -0,0 +1,48 @@
+use anyhow::Result;
+use std::path::{Component, Path, PathBuf};
+
+use crate::error::Error;
+
+fn sanitize_path_string(path_str: &str) -> String {
+    let path = Path::new(path_str);
+
+    if path.is_absolute() || path_str.starts_with("./") || path_str.starts_with("../") {
+        path_str.to_string()
+    } else {
+        format!("./{}", path_str)
+    }
+}
+
+/// Lexically resolves `.` and `..` components without touching the
+/// filesystem, so paths that do not exist yet can still be checked.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+/// Resolves `path_str` against `root`, refusing anything that lands outside it.
+pub fn sanitize_and_resolve_path(root: &Path, path_str: &str) -> Result<PathBuf> {
+    let path_str = sanitize_path_string(path_str);
+    let canonical_root = root.canonicalize()?;
+    let full_path = canonical_root.join(&path_str);
+    let normalized = normalize(&full_path);
+    if !normalized.starts_with(&canonical_root) {
+        return Err(Error::Sandbox(format!(
+            "Unsafe path: '{}' is outside of working directory '{}'",
+            normalized.display(),
+            canonical_root.display()
+        ))
+        .into());
+    }
+
+    Ok(normalized)
+}
@@ -0,0 +1,101 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::compaction::Compaction;
+use crate::message::ChatMessage;
+use crate::tools::ToolDefinition;
+
+/// The transcript of a conversation with the model.
+///
+/// When a log file is attached, the session is written to it after every
+/// message so an interrupted run still leaves a complete history behind.
+///
+/// `messages` always holds the full transcript. Once older turns have been
+/// compacted, requests are built from [`Session::context_messages`]
+/// instead.
+#[derive(Serialize, Deserialize)]
+pub struct Session {
+    pub model: String,
+    pub tools: Vec<ToolDefinition>,
+    pub messages: Vec<ChatMessage>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub compactions: Vec<Compaction>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cancellations: Vec<Cancellation>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub answered_by: Vec<AnsweredBy>,
+    #[serde(skip)]
+    log_file: Option<PathBuf>,
+}
+
+/// Marks where a run was cancelled by the user.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Cancellation {
+    /// Number of messages in the transcript at the time.
+    pub after: usize,
+    /// What was in progress, e.g. "waiting for the model".
+    pub during: String,
+}
+
+/// Which backend and model produced an assistant message.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnsweredBy {
+    /// Index of the message in `messages`.
+    pub message: usize,
+    pub backend: String,
+    pub model: String,
+}
+
+impl Session {
+    pub fn new(
+        system_prompt: impl Into<String>,
+        model: impl Into<String>,
+        tools: Vec<ToolDefinition>,
+    ) -> Self {
+        Session {
+            model: model.into(),
+            tools,
+            messages: vec![ChatMessage::system(system_prompt)],
+            compactions: Vec::new(),
+            cancellations: Vec::new(),
+            answered_by: Vec::new(),
+            log_file: None,
+        }
+    }
+
+    pub fn with_log_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.log_file = Some(path.into());
+        self
+    }
+
+    pub fn log_file(&self) -> Option<&Path> {
+        self.log_file.as_deref()
+    }
+
+    /// The messages to send to the model: the system prompt, the latest
+    /// summary if any, then every message since.
+    pub fn context_messages(&self) -> Vec<ChatMessage> {
+        let Some(compaction) = self.compactions.last() else {
+            return self.messages.clone();
+        };
+        let mut messages = self.messages[..1].to_vec();
+        messages.push(compaction.message());
+        messages.extend_from_slice(&self.messages[compaction.through..]);
+        messages
+    }
+
+    pub fn push(&mut self, message: ChatMessage) -> Result<()> {
+        self.messages.push(message);
+        self.save()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(path) = &self.log_file {
+            let json = serde_json::to_string_pretty(self)?;
+            fs::write(path, json)?;
+        }
+        Ok(())
+    }
+}
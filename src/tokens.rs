@@ -0,0 +1,388 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::error::Error;
+use crate::message::{ChatMessage, Role};
+use crate::tools::ToolDefinition;
+
+/// Tokens a chat API adds around every message for its role and framing.
+const MESSAGE_OVERHEAD: usize = 4;
+
+/// Tokens counted for each attached image. Providers charge by image size
+/// and detail level; this is the cost of a detailed 1024x1024 image.
+const IMAGE_TOKENS: usize = 765;
+
+/// Context windows of well-known models, matched by longest prefix.
+const KNOWN_CONTEXT_WINDOWS: &[(&str, usize)] = &[
+    ("gpt-3.5-turbo", 16_385),
+    ("gpt-4", 8_192),
+    ("gpt-4-32k", 32_768),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-4o", 128_000),
+    ("gpt-4.1", 1_047_576),
+    ("o1", 200_000),
+    ("o3", 200_000),
+    ("o4", 200_000),
+    ("claude", 200_000),
+];
+
+/// Estimates the token count of `text` without a model-specific tokenizer.
+///
+/// Runs of letters and digits count one token per four characters, and
+/// every other non-space character counts as its own token. On English
+/// prose and source code this lands within about 10% of BPE tokenizers
+/// such as `cl100k_base`, erring high on symbol-heavy text.
+pub fn estimate_text(text: &str) -> usize {
+    let mut tokens = 0;
+    let mut word_len: usize = 0;
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            word_len += 1;
+            continue;
+        }
+        tokens += word_len.div_ceil(4);
+        word_len = 0;
+        if !c.is_whitespace() {
+            tokens += 1;
+        }
+    }
+    tokens + word_len.div_ceil(4)
+}
+
+pub fn estimate_message(message: &ChatMessage) -> usize {
+    let content = message.content.as_ref().map_or(0, |content| {
+        estimate_text(&content.as_text()) + IMAGE_TOKENS * content.image_count()
+    });
+    let tool_calls = message.tool_calls.as_ref().map_or(0, |calls| {
+        calls
+            .iter()
+            .map(|call| {
+                estimate_text(&call.function.name) + estimate_text(&call.function.arguments)
+            })
+            .sum()
+    });
+    MESSAGE_OVERHEAD + content + tool_calls
+}
+
+pub fn estimate_tools(tools: &[ToolDefinition]) -> usize {
+    serde_json::to_string(tools).map_or(0, |json| estimate_text(&json))
+}
+
+/// What to do when a request would not fit in the model's context window.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OverflowStrategy {
+    /// Fail before sending, with a report of what uses the budget.
+    #[default]
+    Refuse,
+    /// Leave the oldest turns out of the request until it fits.
+    Trim,
+}
+
+/// Token budgeting settings from the `[budget]` config table.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BudgetConfig {
+    /// Context window sizes by model name, overriding the built-in table.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub context_windows: HashMap<String, usize>,
+    /// Tokens kept free for the model's reply.
+    pub reserve_tokens: usize,
+    pub on_overflow: OverflowStrategy,
+}
+
+impl Default for BudgetConfig {
+    fn default() -> Self {
+        BudgetConfig {
+            context_windows: HashMap::new(),
+            reserve_tokens: 4_096,
+            on_overflow: OverflowStrategy::Refuse,
+        }
+    }
+}
+
+impl BudgetConfig {
+    /// The budget for `model`, or `None` if its context window is unknown.
+    pub fn for_model(&self, model: &str) -> Option<TokenBudget> {
+        let context_window = self.context_windows.get(model).copied().or_else(|| {
+            KNOWN_CONTEXT_WINDOWS
+                .iter()
+                .filter(|(prefix, _)| model.starts_with(prefix))
+                .max_by_key(|(prefix, _)| prefix.len())
+                .map(|(_, window)| *window)
+        })?;
+        Some(TokenBudget {
+            model: model.to_string(),
+            context_window,
+            reserve_tokens: self.reserve_tokens.min(context_window / 2),
+            on_overflow: self.on_overflow,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TokenBudget {
+    pub model: String,
+    pub context_window: usize,
+    pub reserve_tokens: usize,
+    pub on_overflow: OverflowStrategy,
+}
+
+impl TokenBudget {
+    /// Tokens available for the request itself.
+    pub fn limit(&self) -> usize {
+        self.context_window - self.reserve_tokens
+    }
+
+    /// Returns the messages to send, trimmed if allowed, or an error with a
+    /// breakdown of the budget if they cannot fit. Trimmed requests leave
+    /// out older messages; the caller's transcript is not changed.
+    pub fn fit(
+        &self,
+        messages: &[ChatMessage],
+        tools: &[ToolDefinition],
+    ) -> Result<Vec<ChatMessage>> {
+        let report = BudgetReport::new(self, messages, tools);
+        if report.total() <= self.limit() {
+            return Ok(messages.to_vec());
+        }
+        if self.on_overflow == OverflowStrategy::Trim
+            && let Some(trimmed) = self.trim(messages, tools)
+        {
+            return Ok(trimmed);
+        }
+        Err(Error::ContextLength {
+            message: report.to_string(),
+        }
+        .into())
+    }
+
+    /// Drops whole turns after the system prompt, oldest first, keeping the
+    /// latest user message and everything after it.
+    fn trim(&self, messages: &[ChatMessage], tools: &[ToolDefinition]) -> Option<Vec<ChatMessage>> {
+        let (system, rest) = messages.split_first()?;
+        let last_user = rest.iter().rposition(|m| m.role == Role::User)?;
+        let fixed = estimate_tools(tools)
+            + estimate_message(system)
+            + rest[last_user..]
+                .iter()
+                .map(estimate_message)
+                .sum::<usize>();
+        if fixed > self.limit() {
+            return None;
+        }
+
+        // A turn starts at a user message; cutting anywhere else could
+        // separate tool results from the call that produced them.
+        let starts: Vec<usize> = (0..=last_user)
+            .filter(|&i| rest[i].role == Role::User)
+            .collect();
+        let start = starts.into_iter().find(|&start| {
+            fixed
+                + rest[start..last_user]
+                    .iter()
+                    .map(estimate_message)
+                    .sum::<usize>()
+                <= self.limit()
+        })?;
+
+        let mut trimmed = vec![system.clone()];
+        trimmed.extend_from_slice(&rest[start..]);
+        Some(trimmed)
+    }
+}
+
+/// A breakdown of where a request's tokens go.
+pub struct BudgetReport {
+    model: String,
+    context_window: usize,
+    reserve_tokens: usize,
+    entries: Vec<(String, usize)>,
+}
+
+impl BudgetReport {
+    pub fn new(budget: &TokenBudget, messages: &[ChatMessage], tools: &[ToolDefinition]) -> Self {
+        let mut entries = vec![("tool definitions".to_string(), estimate_tools(tools))];
+        for (i, message) in messages.iter().enumerate() {
+            entries.push((describe(i, message), estimate_message(message)));
+        }
+        BudgetReport {
+            model: budget.model.clone(),
+            context_window: budget.context_window,
+            reserve_tokens: budget.reserve_tokens,
+            entries,
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.entries.iter().map(|(_, tokens)| tokens).sum()
+    }
+}
+
+impl fmt::Display for BudgetReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Request needs about {} tokens, but {} has a {}-token context window with {} reserved for the reply.",
+            self.total(),
+            self.model,
+            self.context_window,
+            self.reserve_tokens
+        )?;
+        write!(f, "Largest parts of the request:")?;
+        let mut entries: Vec<&(String, usize)> = self.entries.iter().collect();
+        entries.sort_by_key(|(_, tokens)| Reverse(*tokens));
+        for (label, tokens) in entries.iter().take(8) {
+            write!(f, "\n  {:>8}  {}", tokens, label)?;
+        }
+        Ok(())
+    }
+}
+
+fn describe(index: usize, message: &ChatMessage) -> String {
+    let role = format!("{:?}", message.role).to_lowercase();
+    let preview: String = message
+        .text_content()
+        .unwrap_or_default()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .take(48)
+        .collect();
+    if index == 0 && message.role == Role::System {
+        "system prompt".to_string()
+    } else if message.tool_calls.is_some() {
+        format!("message {} ({}, tool calls)", index, role)
+    } else {
+        format!("message {} ({}): \"{}\"", index, role, preview)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    /// A message of `tokens` tokens, overhead included.
+    fn sized(message: fn(String) -> ChatMessage, tokens: usize) -> ChatMessage {
+        message("a".repeat((tokens - MESSAGE_OVERHEAD) * 4))
+    }
+
+    fn budget(context_window: usize, on_overflow: OverflowStrategy) -> TokenBudget {
+        TokenBudget {
+            model: "test".to_string(),
+            context_window,
+            reserve_tokens: 0,
+            on_overflow,
+        }
+    }
+
+    #[test]
+    fn estimates_words_and_symbols() {
+        assert_eq!(estimate_text(""), 0);
+        assert_eq!(estimate_text("word"), 1);
+        assert_eq!(estimate_text("words"), 2);
+        assert_eq!(estimate_text("fn main() {}"), 6);
+        assert_eq!(estimate_message(&ChatMessage::user("word")), 5);
+    }
+
+    #[test]
+    fn finds_context_windows_by_longest_prefix() {
+        let config = BudgetConfig {
+            context_windows: HashMap::from([("llama3.1:8b".to_string(), 131_072)]),
+            ..BudgetConfig::default()
+        };
+
+        assert_eq!(config.for_model("gpt-4").unwrap().context_window, 8_192);
+        assert_eq!(
+            config.for_model("gpt-4o-mini").unwrap().context_window,
+            128_000
+        );
+        assert_eq!(
+            config.for_model("llama3.1:8b").unwrap().context_window,
+            131_072
+        );
+        assert!(config.for_model("mistral").is_none());
+        // The reserve never takes more than half of a small window.
+        assert_eq!(config.for_model("gpt-4").unwrap().reserve_tokens, 4_096);
+        let small = BudgetConfig {
+            reserve_tokens: 10_000,
+            ..config
+        };
+        assert_eq!(small.for_model("gpt-4").unwrap().reserve_tokens, 4_096);
+    }
+
+    #[test]
+    fn refuses_with_a_report_when_the_request_does_not_fit() {
+        let messages = [
+            sized(ChatMessage::system, 10),
+            sized(ChatMessage::user, 100),
+        ];
+
+        // An empty tool list is sent as `[]`, two tokens.
+        let fitted = budget(112, OverflowStrategy::Refuse).fit(&messages, &[]);
+        assert_eq!(fitted.unwrap().len(), 2);
+
+        let error = budget(111, OverflowStrategy::Refuse)
+            .fit(&messages, &[])
+            .unwrap_err();
+        let Some(Error::ContextLength { message }) = Error::find(&error) else {
+            panic!("{:#}", error);
+        };
+        assert!(
+            message.starts_with("Request needs about 112 tokens"),
+            "{}",
+            message
+        );
+        assert!(message.contains("     100  "), "{}", message);
+    }
+
+    #[test]
+    fn trims_the_oldest_whole_turns() {
+        let mut call = sized(ChatMessage::assistant, 10);
+        call.tool_calls = Some(Vec::new());
+        let messages = [
+            sized(ChatMessage::system, 10),
+            sized(ChatMessage::user, 30),
+            sized(ChatMessage::assistant, 30),
+            sized(ChatMessage::user, 20),
+            call,
+            ChatMessage::tool_result("call_1", "a".repeat(24)),
+            sized(ChatMessage::assistant, 10),
+            sized(ChatMessage::user, 20),
+        ];
+
+        let trimmed = budget(100, OverflowStrategy::Trim)
+            .fit(&messages, &[])
+            .unwrap();
+
+        // The first turn is left out; the second keeps its tool result
+        // with the call that produced it.
+        assert_eq!(trimmed.len(), 6);
+        assert_eq!(trimmed[0].role, Role::System);
+        assert_eq!(trimmed[1].text_content(), messages[3].text_content());
+        assert_eq!(trimmed[3].role, Role::Tool);
+    }
+
+    #[test]
+    fn refuses_when_the_latest_turn_alone_does_not_fit() {
+        let messages = [
+            sized(ChatMessage::system, 10),
+            sized(ChatMessage::user, 30),
+            sized(ChatMessage::user, 100),
+        ];
+
+        let error = budget(100, OverflowStrategy::Trim)
+            .fit(&messages, &[])
+            .unwrap_err();
+
+        assert!(matches!(
+            Error::find(&error),
+            Some(Error::ContextLength { .. })
+        ));
+    }
+}
@@ -1,5 +1,11 @@
+use anyhow::{Result, anyhow};
 use async_trait::async_trait;
 use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::ignore_rules::IgnoreRules;
 
 pub mod create_file;
 use create_file::CreateFile;
@@ -16,31 +22,43 @@ use create_dir::CreateDir;
 pub mod move_file;
 use move_file::MoveFile;
 
+pub mod read_file;
+use read_file::ReadFile;
+
+pub mod remember;
+use remember::Remember;
+
+pub mod repo_map;
+use repo_map::RepoMap;
+
+pub mod search_codebase;
+use search_codebase::SearchCodebase;
+
 pub mod write_file;
 use write_file::WriteFile;
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum ToolType {
     Function,
 }
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ToolDefinition {
     pub r#type: ToolType,
     pub function: ToolFunction,
 }
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ToolFunction {
     pub name: String,
     pub description: String,
     pub parameters: JsonSchemaObject,
 }
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct JsonSchemaObject {
     #[serde(rename = "type")]
     pub schema_type: String,
@@ -49,7 +67,7 @@ pub struct JsonSchemaObject {
     pub required: Vec<String>,
 }
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct JsonSchemaField {
     #[serde(rename = "type")]
     pub field_type: String,
@@ -64,23 +82,124 @@ pub trait Tool: Send + Sync {
     fn name(&self) -> &'static str;
     fn definition(&self) -> ToolDefinition;
 
-    async fn call(&self, args: serde_json::Value) -> anyhow::Result<String>;
+    /// Whether the tool only reads the workspace. Read-only tools are
+    /// refused ignored paths; other tools get a warning instead.
+    fn read_only(&self) -> bool {
+        false
+    }
+
+    /// Whether each call must be approved by the user before it runs.
+    /// Without an approval handler on the agent, such calls are refused.
+    fn requires_approval(&self) -> bool {
+        false
+    }
+
+    async fn call(&self, args: serde_json::Value) -> Result<String>;
+}
+
+/// The set of tools an agent offers the model and dispatches calls to.
+///
+/// The same registry produces both the tool definitions sent with each
+/// request and the lookup used to execute calls, so the two cannot drift.
+#[derive(Default)]
+pub struct ToolRegistry {
+    tools: Vec<Box<dyn Tool>>,
 }
 
-pub fn tools_registry() -> Vec<Box<dyn Tool>> {
-    vec![
-        Box::new(CreateFile),
-        Box::new(DeleteFile),
-        Box::new(AppendFile),
-        Box::new(CreateDir),
-        Box::new(MoveFile),
-        Box::new(WriteFile),
-    ]
+impl ToolRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A registry holding every built-in tool, with project memory and
+    /// ignore rules for the current directory.
+    pub fn builtin() -> Self {
+        let mut registry = Self::new();
+        registry.register(Box::new(CreateFile));
+        registry.register(Box::new(DeleteFile));
+        registry.register(Box::new(AppendFile));
+        registry.register(Box::new(CreateDir));
+        registry.register(Box::new(MoveFile));
+        registry.register(Box::new(WriteFile));
+        registry.register(Box::new(ReadFile));
+        let workspace = env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+        let ignore = IgnoreRules::load(&workspace).unwrap_or_else(|_| IgnoreRules::none());
+        registry.register(Box::new(RepoMap::new(Arc::new(ignore))));
+        registry.register(Box::new(SearchCodebase));
+        registry.register(Box::new(Remember::new(workspace)));
+        registry
+    }
+
+    /// Keeps only the named tools, failing if any name is not registered.
+    pub fn select(&mut self, names: &[String]) -> Result<()> {
+        for name in names {
+            if self.get(name).is_none() {
+                return Err(self.unknown_tool(name));
+            }
+        }
+        self.tools.retain(|t| names.iter().any(|n| n == t.name()));
+        Ok(())
+    }
+
+    /// Removes a tool, failing if it is not registered.
+    pub fn disable(&mut self, name: &str) -> Result<()> {
+        if self.get(name).is_none() {
+            return Err(self.unknown_tool(name));
+        }
+        self.tools.retain(|t| t.name() != name);
+        Ok(())
+    }
+
+    /// Adds a tool, replacing any existing tool with the same name.
+    pub fn register(&mut self, tool: Box<dyn Tool>) {
+        self.tools.retain(|t| t.name() != tool.name());
+        self.tools.push(tool);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Tool> {
+        self.tools
+            .iter()
+            .find(|t| t.name() == name)
+            .map(|t| t.as_ref())
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.tools.iter().map(|t| t.name()).collect()
+    }
+
+    pub fn definitions(&self) -> Vec<ToolDefinition> {
+        self.tools.iter().map(|tool| tool.definition()).collect()
+    }
+
+    pub async fn call(&self, name: &str, args: serde_json::Value) -> Result<String> {
+        let tool = self
+            .get(name)
+            .ok_or_else(|| anyhow!("Unknown tool function: {}", name))?;
+        tool.call(args).await
+    }
+
+    fn unknown_tool(&self, name: &str) -> anyhow::Error {
+        anyhow!(
+            "Unknown tool '{}' (available: {})",
+            name,
+            self.names().join(", ")
+        )
+    }
 }
 
-pub fn tool_definitions() -> Vec<ToolDefinition> {
-    tools_registry()
+/// Tool sets available without any configuration. Sets of the same name in
+/// the config file take precedence.
+pub fn builtin_tool_sets() -> HashMap<String, Vec<String>> {
+    let registry = ToolRegistry::builtin();
+    let full = registry.names().into_iter().map(String::from).collect();
+    let readonly = registry
+        .tools
         .iter()
-        .map(|tool| tool.definition())
-        .collect()
+        .filter(|tool| tool.read_only())
+        .map(|tool| tool.name().to_string())
+        .collect();
+    HashMap::from([
+        ("full".to_string(), full),
+        ("readonly".to_string(), readonly),
+    ])
 }
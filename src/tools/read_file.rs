@@ -0,0 +1,59 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::fs;
+
+use crate::tools::{
+    JsonSchemaField, JsonSchemaObject, Tool, ToolDefinition, ToolFunction, ToolType,
+};
+
+use std::collections::HashMap;
+
+#[derive(Debug, Deserialize)]
+pub struct ReadFileParams {
+    pub path: String,
+}
+
+pub struct ReadFile;
+
+#[async_trait]
+impl Tool for ReadFile {
+    fn name(&self) -> &'static str {
+        "read_file"
+    }
+
+    fn read_only(&self) -> bool {
+        true
+    }
+
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition {
+            r#type: ToolType::Function,
+            function: ToolFunction {
+                name: self.name().to_string(),
+                description: "Read the contents of a text file at the given path.".to_string(),
+                parameters: JsonSchemaObject {
+                    schema_type: "object".to_string(),
+                    properties: HashMap::from([(
+                        "path".to_string(),
+                        JsonSchemaField {
+                            field_type: "string".to_string(),
+                            description: Some("The path to the file to read.".to_string()),
+                            enum_values: None,
+                        },
+                    )]),
+                    required: vec!["path".to_string()],
+                },
+            },
+        }
+    }
+
+    async fn call(&self, args: Value) -> Result<String> {
+        let params: ReadFileParams = serde_json::from_value(args)?;
+
+        let contents = fs::read_to_string(&params.path).await?;
+
+        Ok(contents)
+    }
+}
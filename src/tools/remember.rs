@@ -0,0 +1,88 @@
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::PathBuf;
+
+use crate::memory::{MemoryScope, memory_path, remember};
+use crate::tools::{
+    JsonSchemaField, JsonSchemaObject, Tool, ToolDefinition, ToolFunction, ToolType,
+};
+
+use std::collections::HashMap;
+
+#[derive(Debug, Deserialize)]
+pub struct RememberParams {
+    pub fact: String,
+    #[serde(default)]
+    pub scope: MemoryScope,
+}
+
+/// Saves facts to the memory files. Project memory goes to the
+/// `JUNIOR.md` of `workspace`, where [`load_memory`] reads it back.
+///
+/// [`load_memory`]: crate::memory::load_memory
+pub struct Remember {
+    pub workspace: PathBuf,
+}
+
+impl Remember {
+    pub fn new(workspace: impl Into<PathBuf>) -> Self {
+        Remember {
+            workspace: workspace.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl Tool for Remember {
+    fn name(&self) -> &'static str {
+        "remember"
+    }
+
+    fn requires_approval(&self) -> bool {
+        true
+    }
+
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition {
+            r#type: ToolType::Function,
+            function: ToolFunction {
+                name: self.name().to_string(),
+                description: "Save a lasting fact, such as a project convention or a user preference, to the memory file loaded into every future session. The user is asked to approve it first.".to_string(),
+                parameters: JsonSchemaObject {
+                    schema_type: "object".to_string(),
+                    properties: HashMap::from([
+                        (
+                            "fact".to_string(),
+                            JsonSchemaField {
+                                field_type: "string".to_string(),
+                                description: Some("The fact to remember, as one short sentence.".to_string()),
+                                enum_values: None,
+                            },
+                        ),
+                        (
+                            "scope".to_string(),
+                            JsonSchemaField {
+                                field_type: "string".to_string(),
+                                description: Some("'project' for this project's JUNIOR.md (the default), or 'user' for the user's own memory.".to_string()),
+                                enum_values: Some(vec!["project".to_string(), "user".to_string()]),
+                            },
+                        ),
+                    ]),
+                    required: vec!["fact".to_string()],
+                },
+            },
+        }
+    }
+
+    async fn call(&self, args: Value) -> Result<String> {
+        let params: RememberParams = serde_json::from_value(args)?;
+        let path = memory_path(&self.workspace, params.scope)
+            .ok_or_else(|| anyhow!("Could not find the home directory"))?;
+
+        remember(&path, &params.fact)?;
+
+        Ok(format!("Remembered in {}", path.display()))
+    }
+}
@@ -0,0 +1,104 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::ignore_rules::IgnoreRules;
+use crate::repo_map::{self, DEFAULT_MAX_TOKENS};
+use crate::tools::{
+    JsonSchemaField, JsonSchemaObject, Tool, ToolDefinition, ToolFunction, ToolType,
+};
+
+use std::collections::HashMap;
+
+#[derive(Debug, Deserialize)]
+pub struct RepoMapParams {
+    pub path: String,
+    #[serde(default)]
+    pub query: String,
+    pub max_tokens: Option<usize>,
+}
+
+/// Outlines directories, skipping what `ignore` excludes. The rules are
+/// shared with the agent, so they are loaded once per workspace.
+pub struct RepoMap {
+    pub ignore: Arc<IgnoreRules>,
+}
+
+impl RepoMap {
+    pub fn new(ignore: Arc<IgnoreRules>) -> Self {
+        RepoMap { ignore }
+    }
+}
+
+#[async_trait]
+impl Tool for RepoMap {
+    fn name(&self) -> &'static str {
+        "repo_map"
+    }
+
+    fn read_only(&self) -> bool {
+        true
+    }
+
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition {
+            r#type: ToolType::Function,
+            function: ToolFunction {
+                name: self.name().to_string(),
+                description: "Outline a directory: its subdirectories, and the files in it with their top-level functions, types and classes, most relevant to the query first.".to_string(),
+                parameters: JsonSchemaObject {
+                    schema_type: "object".to_string(),
+                    properties: HashMap::from([
+                        (
+                            "path".to_string(),
+                            JsonSchemaField {
+                                field_type: "string".to_string(),
+                                description: Some(
+                                    "The directory to outline; use '.' for the whole project."
+                                        .to_string(),
+                                ),
+                                enum_values: None,
+                            },
+                        ),
+                        (
+                            "query".to_string(),
+                            JsonSchemaField {
+                                field_type: "string".to_string(),
+                                description: Some(
+                                    "Words describing what you are looking for, used to rank files."
+                                        .to_string(),
+                                ),
+                                enum_values: None,
+                            },
+                        ),
+                        (
+                            "max_tokens".to_string(),
+                            JsonSchemaField {
+                                field_type: "integer".to_string(),
+                                description: Some(format!(
+                                    "Approximate size limit of the outline (default {}).",
+                                    DEFAULT_MAX_TOKENS
+                                )),
+                                enum_values: None,
+                            },
+                        ),
+                    ]),
+                    required: vec!["path".to_string()],
+                },
+            },
+        }
+    }
+
+    async fn call(&self, args: Value) -> Result<String> {
+        let params: RepoMapParams = serde_json::from_value(args)?;
+        repo_map::build(
+            Path::new(&params.path),
+            &self.ignore,
+            &params.query,
+            params.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+        )
+    }
+}
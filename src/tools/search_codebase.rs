@@ -0,0 +1,126 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+use crate::context::language_for;
+use crate::index::{CodeIndex, find_index_root};
+use crate::tools::{
+    JsonSchemaField, JsonSchemaObject, Tool, ToolDefinition, ToolFunction, ToolType,
+};
+
+use std::collections::HashMap;
+
+const DEFAULT_LIMIT: usize = 5;
+const MAX_LIMIT: usize = 20;
+
+#[derive(Debug, Deserialize)]
+pub struct SearchCodebaseParams {
+    pub path: String,
+    pub query: String,
+    pub limit: Option<usize>,
+}
+
+pub struct SearchCodebase;
+
+#[async_trait]
+impl Tool for SearchCodebase {
+    fn name(&self) -> &'static str {
+        "search_codebase"
+    }
+
+    fn read_only(&self) -> bool {
+        true
+    }
+
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition {
+            r#type: ToolType::Function,
+            function: ToolFunction {
+                name: self.name().to_string(),
+                description: "Search the project's code index for the passages that best match a query, such as names of functions or a description of behavior.".to_string(),
+                parameters: JsonSchemaObject {
+                    schema_type: "object".to_string(),
+                    properties: HashMap::from([
+                        (
+                            "path".to_string(),
+                            JsonSchemaField {
+                                field_type: "string".to_string(),
+                                description: Some(
+                                    "Directory to search in; use '.' for the whole project."
+                                        .to_string(),
+                                ),
+                                enum_values: None,
+                            },
+                        ),
+                        (
+                            "query".to_string(),
+                            JsonSchemaField {
+                                field_type: "string".to_string(),
+                                description: Some("What to look for.".to_string()),
+                                enum_values: None,
+                            },
+                        ),
+                        (
+                            "limit".to_string(),
+                            JsonSchemaField {
+                                field_type: "integer".to_string(),
+                                description: Some(format!(
+                                    "Number of passages to return (default {}, at most {}).",
+                                    DEFAULT_LIMIT, MAX_LIMIT
+                                )),
+                                enum_values: None,
+                            },
+                        ),
+                    ]),
+                    required: vec!["path".to_string(), "query".to_string()],
+                },
+            },
+        }
+    }
+
+    async fn call(&self, args: Value) -> Result<String> {
+        let params: SearchCodebaseParams = serde_json::from_value(args)?;
+        let path = Path::new(&params.path).canonicalize()?;
+        let root = find_index_root(&path)?;
+        let index = CodeIndex::load(&root)?;
+        let prefix = path
+            .strip_prefix(&root)
+            .unwrap_or(&path)
+            .to_string_lossy()
+            .to_string();
+        let limit = params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+        let hits = index.search(&params.query, &prefix, limit);
+        if hits.is_empty() {
+            return Ok(format!("No matches for '{}'.", params.query));
+        }
+
+        let mut output = Vec::new();
+        for hit in hits {
+            // The index stores terms, not text, so passages come from the
+            // file as it is now; it may have changed since indexing.
+            let Ok(text) = fs::read_to_string(root.join(&hit.path)) else {
+                continue;
+            };
+            let passage: Vec<&str> = text
+                .lines()
+                .skip(hit.start_line - 1)
+                .take(hit.end_line + 1 - hit.start_line)
+                .collect();
+            output.push(format!(
+                "{}:{}-{} (score {:.2})\n```{}\n{}\n```",
+                hit.path,
+                hit.start_line,
+                hit.end_line,
+                hit.score,
+                language_for(&hit.path),
+                passage.join("\n")
+            ));
+        }
+
+        Ok(output.join("\n\n"))
+    }
+}
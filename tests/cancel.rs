@@ -0,0 +1,160 @@
+//! Cancelling a run while it waits for the model or runs a tool.
+
+mod common;
+
+use async_trait::async_trait;
+use common::StandIn;
+use junior::cancel::CancelToken;
+use junior::error::Error;
+use junior::llm::Backend;
+use junior::tools::{JsonSchemaObject, ToolFunction, ToolType};
+use junior::{Agent, Session, Tool, ToolDefinition, ToolRegistry};
+use std::collections::HashMap;
+use std::net::TcpListener;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const TWO_CALLS: &str = r#"{"choices":[{"message":{"role":"assistant","content":null,"tool_calls":[{"id":"call_1","type":"function","function":{"name":"stall","arguments":"{}"}},{"id":"call_2","type":"function","function":{"name":"stall","arguments":"{}"}}]}}]}"#;
+
+/// A tool that cancels the run it is part of, then finishes only if it is
+/// not read-only.
+struct Stall {
+    cancel: Arc<CancelToken>,
+    read_only: bool,
+}
+
+#[async_trait]
+impl Tool for Stall {
+    fn name(&self) -> &'static str {
+        "stall"
+    }
+
+    fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition {
+            r#type: ToolType::Function,
+            function: ToolFunction {
+                name: self.name().to_string(),
+                description: "Takes its time.".to_string(),
+                parameters: JsonSchemaObject {
+                    schema_type: "object".to_string(),
+                    properties: HashMap::new(),
+                    required: Vec::new(),
+                },
+            },
+        }
+    }
+
+    async fn call(&self, _args: serde_json::Value) -> anyhow::Result<String> {
+        self.cancel.cancel();
+        if self.read_only {
+            std::future::pending::<()>().await;
+        }
+        Ok("done".to_string())
+    }
+}
+
+fn agent(endpoint: String, workspace: &Path) -> (Agent, Arc<CancelToken>) {
+    let cancel = Arc::new(CancelToken::default());
+    let mut agent = Agent::new("gpt-4", "", "");
+    agent.backend = Backend::new(endpoint, "secret");
+    agent.set_workspace(workspace.to_path_buf()).unwrap();
+    agent.tools = ToolRegistry::new();
+    agent.budget = None;
+    agent.compaction = None;
+    agent.cancel = Some(Arc::clone(&cancel));
+    (agent, cancel)
+}
+
+/// The saved transcript, as the CLI leaves it behind.
+fn saved(workspace: &Path) -> Session {
+    let json = std::fs::read_to_string(workspace.join("session.json")).unwrap();
+    serde_json::from_str(&json).unwrap()
+}
+
+#[tokio::test]
+async fn cancelling_abandons_the_request_and_saves_the_session() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = format!(
+        "http://{}/v1/chat/completions",
+        listener.local_addr().unwrap()
+    );
+    // Accepts the connection but never replies.
+    let _silent = thread::spawn(move || listener.accept().map(|(stream, _)| stream));
+    let workspace = tempfile::tempdir().unwrap();
+    let (agent, cancel) = agent(endpoint, workspace.path());
+    let mut session = agent
+        .new_session()
+        .with_log_file(workspace.path().join("session.json"));
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        cancel.cancel();
+    });
+
+    let error = agent.run(&mut session, "hi").await.unwrap_err();
+
+    assert!(matches!(Error::find(&error), Some(Error::Cancelled)));
+    let saved = saved(workspace.path());
+    assert_eq!(saved.messages.len(), 2);
+    assert_eq!(saved.cancellations.len(), 1);
+    assert_eq!(saved.cancellations[0].after, 2);
+    assert_eq!(saved.cancellations[0].during, "waiting for the model");
+}
+
+async fn cancel_during_tool(read_only: bool) -> (Session, Vec<String>) {
+    let server = StandIn::scripted(vec![("200 OK", "", TWO_CALLS)]);
+    let workspace = tempfile::tempdir().unwrap();
+    let (mut agent, cancel) = agent(server.endpoint(), workspace.path());
+    agent.tools.register(Box::new(Stall { cancel, read_only }));
+    let mut session = agent
+        .new_session()
+        .with_log_file(workspace.path().join("session.json"));
+
+    let error = agent.run(&mut session, "stall twice").await.unwrap_err();
+
+    assert!(matches!(Error::find(&error), Some(Error::Cancelled)));
+    assert_eq!(server.received().len(), 1);
+    let saved = saved(workspace.path());
+    assert_eq!(saved.cancellations.len(), 1);
+    assert_eq!(saved.cancellations[0].after, saved.messages.len());
+    assert_eq!(saved.cancellations[0].during, "running stall");
+    let results = saved.messages[3..]
+        .iter()
+        .map(|message| message.text_content().unwrap().to_string())
+        .collect();
+    (saved, results)
+}
+
+#[tokio::test]
+async fn a_read_only_tool_is_stopped_and_every_call_gets_a_result() {
+    let (session, results) = cancel_during_tool(true).await;
+
+    assert_eq!(results, ["Cancelled by the user", "Cancelled by the user"]);
+    assert_eq!(session.messages[4].tool_call_id.as_deref(), Some("call_2"));
+}
+
+#[tokio::test]
+async fn a_tool_that_changes_files_finishes_before_the_run_stops() {
+    let (_, results) = cancel_during_tool(false).await;
+
+    assert_eq!(results, ["done", "Cancelled by the user"]);
+}
+
+#[tokio::test]
+async fn the_next_run_starts_uncancelled() {
+    let server = StandIn::start();
+    let workspace = tempfile::tempdir().unwrap();
+    let (agent, cancel) = agent(server.endpoint(), workspace.path());
+    let mut session = agent.new_session();
+    cancel.cancel();
+
+    let reply = agent.run(&mut session, "hi").await.unwrap();
+
+    assert_eq!(reply.text_content().unwrap(), "ok");
+    assert!(session.cancellations.is_empty());
+}
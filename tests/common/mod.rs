@@ -0,0 +1,133 @@
+//! A stand-in chat completions server on a local port.
+
+// Each test binary uses a different part of the stand-in.
+#![allow(dead_code)]
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use junior::http::{HttpConfig, RetryConfig};
+
+pub const REPLY: &str = r#"{"choices":[{"message":{"role":"assistant","content":"ok"}}]}"#;
+
+/// A request as the server saw it.
+#[derive(Debug, Clone)]
+pub struct Received {
+    pub connection: usize,
+    pub request_line: String,
+    pub headers: BTreeMap<String, String>,
+    pub body: String,
+}
+
+/// A canned response: status line, extra headers and body.
+pub type Scripted = (&'static str, &'static str, &'static str);
+
+/// Answers requests with the scripted responses in order, then with
+/// [`REPLY`], and records them. Connections are kept alive, so reuse
+/// shows up as several requests on one connection.
+pub struct StandIn {
+    pub url: String,
+    received: Arc<Mutex<Vec<Received>>>,
+}
+
+impl StandIn {
+    pub fn start() -> Self {
+        Self::scripted(Vec::new())
+    }
+
+    pub fn scripted(responses: Vec<Scripted>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&received);
+        let script = Arc::new(Mutex::new(responses));
+        thread::spawn(move || {
+            for (connection, stream) in listener.incoming().enumerate() {
+                let log = Arc::clone(&log);
+                let script = Arc::clone(&script);
+                thread::spawn(move || serve(connection, stream.unwrap(), &log, &script));
+            }
+        });
+        StandIn { url, received }
+    }
+
+    pub fn endpoint(&self) -> String {
+        format!("{}/v1/chat/completions", self.url)
+    }
+
+    pub fn received(&self) -> Vec<Received> {
+        self.received.lock().unwrap().clone()
+    }
+}
+
+fn serve(
+    connection: usize,
+    stream: TcpStream,
+    log: &Mutex<Vec<Received>>,
+    script: &Mutex<Vec<Scripted>>,
+) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+    loop {
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+            return;
+        }
+        let mut headers = BTreeMap::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line.split_once(':').unwrap();
+            headers.insert(name.to_lowercase(), value.trim().to_string());
+        }
+        let length: usize = headers
+            .get("content-length")
+            .map_or(0, |n| n.parse().unwrap());
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+
+        log.lock().unwrap().push(Received {
+            connection,
+            request_line: request_line.trim_end().to_string(),
+            headers,
+            body: String::from_utf8_lossy(&body).to_string(),
+        });
+        let (status, extra_headers, body) = {
+            let mut script = script.lock().unwrap();
+            if script.is_empty() {
+                ("200 OK", "", REPLY)
+            } else {
+                script.remove(0)
+            }
+        };
+        write!(
+            writer,
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\n{}Content-Length: {}\r\n\r\n{}",
+            status,
+            extra_headers,
+            body.len(),
+            body
+        )
+        .unwrap();
+    }
+}
+
+/// Retries without waiting long, so that tests stay fast.
+pub fn quick_retries(max_retries: u32) -> HttpConfig {
+    HttpConfig {
+        retry: RetryConfig {
+            max_retries,
+            initial_backoff_ms: 1,
+            max_backoff_ms: 10,
+            ..RetryConfig::default()
+        },
+        ..HttpConfig::default()
+    }
+}
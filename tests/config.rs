@@ -0,0 +1,147 @@
+//! Where settings come from, checked through `junior config show`.
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+use tempfile::TempDir;
+
+/// A home directory with a global config and a project below it, whose
+/// own config sets a few values.
+fn setup() -> TempDir {
+    let home = tempfile::tempdir().unwrap();
+    fs::write(
+        home.path().join(".junior.toml"),
+        r#"
+        api_key = "secret"
+        model = "global-model"
+        endpoint = "https://api.openai.com/v1/chat/completions"
+        history_directory_path = "/tmp/history"
+        temperature = 0.1
+        tool_set = "full"
+
+        [profiles.fast]
+        model = "profile-model"
+        temperature = 0.5
+        "#,
+    )
+    .unwrap();
+    let project = home.path().join("project");
+    fs::create_dir_all(project.join("src")).unwrap();
+    fs::write(
+        project.join(".junior.toml"),
+        "model = \"project-model\"\ntool_set = \"readonly\"\nsystem_prompt = \"prompts/review.md\"\n",
+    )
+    .unwrap();
+    home
+}
+
+fn config_show(home: &Path, env: &[(&str, &str)]) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_junior"));
+    command
+        .arg("config")
+        .arg("show")
+        .current_dir(home.join("project/src"))
+        .env("HOME", home)
+        .stdin(Stdio::null());
+    for (var, _) in std::env::vars().filter(|(var, _)| var.starts_with("JUNIOR_")) {
+        command.env_remove(var);
+    }
+    command.envs(env.iter().copied());
+    command.output().unwrap()
+}
+
+/// The `value  # source` part of the line for `key`.
+fn shown(output: &Output, key: &str) -> String {
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let prefix = format!("{} = ", key);
+    stdout
+        .lines()
+        .find_map(|line| line.strip_prefix(&prefix))
+        .unwrap_or_else(|| panic!("{} not shown in:\n{}", key, stdout))
+        .to_string()
+}
+
+#[test]
+fn layers_apply_from_global_to_environment() {
+    let home = setup();
+    let home = home.path();
+    let global = home.join(".junior.toml");
+    let project = home.join("project/.junior.toml");
+
+    let output = config_show(home, &[]);
+    assert_eq!(
+        shown(&output, "model"),
+        format!("\"project-model\"  # {}", project.display())
+    );
+    assert_eq!(
+        shown(&output, "temperature"),
+        format!("0.1  # {}", global.display())
+    );
+    assert_eq!(
+        shown(&output, "system_prompt"),
+        format!(
+            "\"{}\"  # {}",
+            home.join("project/prompts/review.md").display(),
+            project.display()
+        )
+    );
+
+    let output = config_show(home, &[("JUNIOR_PROFILE", "fast")]);
+    assert_eq!(shown(&output, "model"), "\"profile-model\"  # profile fast");
+    assert_eq!(shown(&output, "temperature"), "0.5  # profile fast");
+    assert_eq!(
+        shown(&output, "tool_set"),
+        format!("\"readonly\"  # {}", project.display())
+    );
+
+    let env = [("JUNIOR_PROFILE", "fast"), ("JUNIOR_MODEL", "env-model")];
+    let output = config_show(home, &env);
+    assert_eq!(shown(&output, "model"), "\"env-model\"  # $JUNIOR_MODEL");
+    assert_eq!(shown(&output, "temperature"), "0.5  # profile fast");
+}
+
+#[test]
+fn environment_variables_are_parsed_by_type() {
+    let home = setup();
+    let home = home.path();
+
+    let env = [
+        ("JUNIOR_TEMPERATURE", "0.3"),
+        ("JUNIOR_MAX_TOKENS", "512"),
+        ("JUNIOR_PARALLEL_TOOL_CALLS", "false"),
+    ];
+    let output = config_show(home, &env);
+    assert_eq!(shown(&output, "temperature"), "0.3  # $JUNIOR_TEMPERATURE");
+    assert_eq!(shown(&output, "max_tokens"), "512  # $JUNIOR_MAX_TOKENS");
+    assert_eq!(
+        shown(&output, "parallel_tool_calls"),
+        "false  # $JUNIOR_PARALLEL_TOOL_CALLS"
+    );
+
+    let output = config_show(home, &[("JUNIOR_MAX_TOKENS", "lots")]);
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Invalid $JUNIOR_MAX_TOKENS: expected a number, got 'lots'"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn api_key_cmd_is_shown_without_running_it() {
+    let home = setup();
+    let home = home.path();
+    let config = home.join(".junior.toml");
+    let text = fs::read_to_string(&config).unwrap();
+    fs::write(
+        &config,
+        text.replace("api_key = \"secret\"", "api_key_cmd = \"exit 1\""),
+    )
+    .unwrap();
+
+    let output = config_show(home, &[]);
+    assert_eq!(shown(&output, "api_key"), "(from api_key_cmd)  # default");
+}
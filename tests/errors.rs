@@ -0,0 +1,112 @@
+//! Classifying provider error responses.
+
+mod common;
+
+use common::{StandIn, quick_retries};
+use junior::ChatMessage;
+use junior::error::Error;
+use junior::llm::{Backend, GenerationParams, send_to_llm};
+use reqwest::StatusCode;
+
+fn classify(status: u16, body: &str) -> Error {
+    Error::from_response(StatusCode::from_u16(status).unwrap(), body)
+}
+
+#[test]
+fn classifies_openai_style_errors() {
+    let error = classify(
+        401,
+        r#"{"error":{"message":"Incorrect API key provided","type":"invalid_request_error","code":"invalid_api_key"}}"#,
+    );
+    assert!(matches!(error, Error::Auth { .. }), "{:?}", error);
+    assert_eq!(error.exit_code(), 3);
+
+    let error = classify(
+        429,
+        r#"{"error":{"message":"Rate limit reached for gpt-4","type":"requests","code":"rate_limit_exceeded"}}"#,
+    );
+    assert!(matches!(error, Error::RateLimited { .. }), "{:?}", error);
+    assert_eq!(
+        error.to_string(),
+        "Rate limited by the provider: Rate limit reached for gpt-4"
+    );
+
+    let error = classify(
+        429,
+        r#"{"error":{"message":"You exceeded your current quota, please check your plan and billing details.","type":"insufficient_quota","code":"insufficient_quota"}}"#,
+    );
+    assert!(matches!(error, Error::Provider { .. }), "{:?}", error);
+    assert!(!error.is_retryable());
+    assert_eq!(error.exit_code(), 8);
+
+    let error = classify(
+        400,
+        r#"{"error":{"message":"This model's maximum context length is 8192 tokens.","type":"invalid_request_error","code":"context_length_exceeded"}}"#,
+    );
+    assert!(matches!(error, Error::ContextLength { .. }), "{:?}", error);
+    assert_eq!(error.exit_code(), 5);
+}
+
+#[test]
+fn classifies_azure_and_anthropic_style_errors() {
+    let error = classify(
+        400,
+        r#"{"error":{"message":"The response was filtered","code":"content_filter","innererror":{"code":"ResponsibleAIPolicyViolation"}}}"#,
+    );
+    assert!(matches!(error, Error::ContentFilter { .. }), "{:?}", error);
+
+    let error = classify(
+        400,
+        r#"{"type":"error","error":{"type":"invalid_request_error","message":"prompt is too long: 210000 tokens > 200000 maximum"}}"#,
+    );
+    assert!(matches!(error, Error::ContextLength { .. }), "{:?}", error);
+
+    let error = classify(
+        529,
+        r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#,
+    );
+    assert!(error.is_retryable());
+    assert!(matches!(error, Error::Unavailable { .. }), "{:?}", error);
+}
+
+#[test]
+fn falls_back_to_the_status_for_unknown_bodies() {
+    let error = classify(404, "no such deployment");
+    let Error::Provider { status, message } = &error else {
+        panic!("{:?}", error);
+    };
+    assert_eq!(*status, StatusCode::NOT_FOUND);
+    assert_eq!(message, "no such deployment");
+    assert_eq!(error.exit_code(), 8);
+    assert!(!error.is_retryable());
+
+    assert!(matches!(classify(403, ""), Error::Auth { .. }));
+    assert!(matches!(classify(502, "<html>"), Error::Unavailable { .. }));
+}
+
+#[tokio::test]
+async fn a_withheld_response_is_a_content_filter_error() {
+    let server = StandIn::scripted(vec![(
+        "200 OK",
+        "",
+        r#"{"choices":[{"message":{"role":"assistant","content":null},"finish_reason":"content_filter"}]}"#,
+    )]);
+    let backend = Backend::with_http(server.endpoint(), "secret", &quick_retries(3)).unwrap();
+
+    let error = send_to_llm(
+        &backend,
+        &[ChatMessage::user("hi")],
+        "gpt-4",
+        &[],
+        &GenerationParams::default(),
+        &|_| {},
+    )
+    .await
+    .unwrap_err();
+
+    assert!(matches!(
+        Error::find(&error),
+        Some(Error::ContentFilter { .. })
+    ));
+    assert_eq!(server.received().len(), 1);
+}
@@ -0,0 +1,118 @@
+//! Exit codes of the CLI for the documented classes of failure.
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+use common::StandIn;
+use tempfile::TempDir;
+
+/// A home directory with a config pointing at `endpoint`, and a workspace
+/// inside it so that no project config above is picked up.
+fn home(endpoint: &str) -> TempDir {
+    let home = tempfile::tempdir().unwrap();
+    fs::write(
+        home.path().join(".junior.toml"),
+        format!(
+            "api_key = \"secret\"\nmodel = \"gpt-4\"\nendpoint = \"{}\"\nhistory_directory_path = \"{}\"\n",
+            endpoint,
+            home.path().join("history").display()
+        ),
+    )
+    .unwrap();
+    fs::create_dir(home.path().join("ws")).unwrap();
+    home
+}
+
+fn junior(home: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_junior"))
+        .args(args)
+        .current_dir(home.join("ws"))
+        .env("HOME", home)
+        .env_remove("JUNIOR_API_KEY")
+        .env_remove("JUNIOR_PROFILE")
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+fn exit_code(home: &Path, args: &[&str]) -> i32 {
+    junior(home, args).status.code().unwrap()
+}
+
+#[test]
+fn command_line_mistakes_exit_with_2() {
+    let server = StandIn::start();
+    let home = home(&server.endpoint());
+    let home = home.path();
+
+    assert_eq!(exit_code(home, &[]), 2, "no prompt");
+    assert_eq!(exit_code(home, &["hi", "there"]), 2, "unexpected arguments");
+    assert_eq!(
+        exit_code(home, &["hi", "--tools", "nope"]),
+        2,
+        "unknown tool set"
+    );
+    assert_eq!(
+        exit_code(home, &["hi", "--disable", "nope"]),
+        2,
+        "unknown tool"
+    );
+    assert_eq!(exit_code(home, &["/nope"]), 2, "unknown command");
+    assert_eq!(
+        exit_code(home, &["--prompt-file", "missing.md"]),
+        2,
+        "missing prompt file"
+    );
+    assert_eq!(exit_code(home, &["hi", "--bogus"]), 2, "unknown flag");
+    assert_eq!(
+        exit_code(home, &["hi", "-f", "missing.rs"]),
+        2,
+        "missing file"
+    );
+    assert_eq!(
+        exit_code(home, &["explain @src/missing.rs"]),
+        2,
+        "unresolved mention"
+    );
+    assert_eq!(exit_code(home, &["hi", "-f", "*.nope"]), 2, "empty glob");
+    assert_eq!(
+        exit_code(home, &["hi", "--profile", "nope"]),
+        2,
+        "unknown profile"
+    );
+    assert!(server.received().is_empty());
+}
+
+#[test]
+fn prints_the_error_and_a_hint() {
+    let server = StandIn::scripted(vec![(
+        "401 Unauthorized",
+        "",
+        r#"{"error":{"message":"Incorrect API key provided","code":"invalid_api_key"}}"#,
+    )]);
+    let home = home(&server.endpoint());
+
+    let output = junior(home.path(), &["hi"]);
+
+    assert_eq!(output.status.code(), Some(3));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("error: The provider rejected the API key (401 Unauthorized): Incorrect API key provided\nhint: "),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn provider_and_sandbox_failures_have_their_own_codes() {
+    let server = StandIn::scripted(vec![("400 Bad Request", "", "bad model")]);
+    let home = home(&server.endpoint());
+    let home = home.path();
+
+    assert_eq!(exit_code(home, &["hi", "--no-tools"]), 8);
+    assert_eq!(exit_code(home, &["hi", "-f", "../.junior.toml"]), 9);
+    assert_eq!(exit_code(home, &["hi", "--no-tools"]), 0);
+}
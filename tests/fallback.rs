@@ -0,0 +1,123 @@
+//! Falling back to other backends when the primary one is unavailable.
+
+mod common;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use common::{StandIn, quick_retries};
+use junior::agent::Fallback;
+use junior::llm::Backend;
+use junior::{Agent, ToolRegistry};
+
+fn backend(name: &str, server: &StandIn) -> Backend {
+    Backend {
+        name: name.to_string(),
+        ..Backend::with_http(server.endpoint(), "secret", &quick_retries(1)).unwrap()
+    }
+}
+
+fn agent(primary: &StandIn, fallbacks: &[(&str, &StandIn)]) -> Agent {
+    let mut agent = Agent::new("primary-model", "", "");
+    agent.backend = backend("primary", primary);
+    agent.fallbacks = fallbacks
+        .iter()
+        .map(|(name, server)| Fallback::new(format!("{}-model", name), backend(name, server)))
+        .collect();
+    agent.tools = ToolRegistry::new();
+    agent.budget = None;
+    agent.compaction = None;
+    agent
+}
+
+fn model_of(body: &str) -> String {
+    let body: serde_json::Value = serde_json::from_str(body).unwrap();
+    body["model"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn falls_back_when_the_primary_stays_unavailable() {
+    let primary = StandIn::scripted(vec![("503 Service Unavailable", "", "down"); 2]);
+    let backup = StandIn::scripted(vec![("429 Too Many Requests", "", "busy"); 2]);
+    let local = StandIn::start();
+    let agent = agent(&primary, &[("backup", &backup), ("local", &local)]);
+    let mut session = agent.new_session();
+
+    let reply = agent.run(&mut session, "hi").await.unwrap();
+
+    assert_eq!(reply.text_content().unwrap(), "ok");
+    assert_eq!(primary.received().len(), 2);
+    assert_eq!(backup.received().len(), 2);
+    assert_eq!(model_of(&local.received()[0].body), "local-model");
+    let answered_by = &session.answered_by[0];
+    assert_eq!(answered_by.message, session.messages.len() - 1);
+    assert_eq!(answered_by.backend, "local");
+    assert_eq!(answered_by.model, "local-model");
+}
+
+#[tokio::test]
+async fn records_the_primary_when_it_answers() {
+    let primary = StandIn::start();
+    let backup = StandIn::start();
+    let agent = agent(&primary, &[("backup", &backup)]);
+    let mut session = agent.new_session();
+
+    agent.run(&mut session, "hi").await.unwrap();
+
+    assert!(backup.received().is_empty());
+    assert_eq!(session.answered_by[0].backend, "primary");
+    assert_eq!(session.answered_by[0].model, "primary-model");
+}
+
+#[tokio::test]
+async fn does_not_fall_back_on_other_errors() {
+    let primary = StandIn::scripted(vec![("400 Bad Request", "", "bad request")]);
+    let backup = StandIn::start();
+    let agent = agent(&primary, &[("backup", &backup)]);
+    let mut session = agent.new_session();
+
+    let error = agent.run(&mut session, "hi").await.unwrap_err();
+
+    assert!(error.to_string().contains("400 Bad Request"));
+    assert!(backup.received().is_empty());
+    assert!(session.answered_by.is_empty());
+}
+
+#[tokio::test]
+async fn fails_with_the_last_error_when_every_backend_is_unavailable() {
+    let primary = StandIn::scripted(vec![("503 Service Unavailable", "", "down"); 2]);
+    let backup = StandIn::scripted(vec![("502 Bad Gateway", "", "gateway"); 2]);
+    let agent = agent(&primary, &[("backup", &backup)]);
+    let mut session = agent.new_session();
+
+    let error = agent.run(&mut session, "hi").await.unwrap_err();
+
+    assert!(error.to_string().contains("502 Bad Gateway"));
+}
+
+#[tokio::test]
+async fn loads_fallbacks_only_when_needed_and_skips_broken_ones() {
+    let primary = StandIn::scripted(vec![("503 Service Unavailable", "", "down"); 4]);
+    let local = StandIn::start();
+    let loads = Arc::new(AtomicUsize::new(0));
+    let mut agent = agent(&primary, &[("local", &local)]);
+    let counter = Arc::clone(&loads);
+    agent.fallbacks.insert(
+        0,
+        Fallback::lazy(
+            "broken",
+            Box::new(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Err(anyhow::anyhow!("api_key_cmd `false` failed"))
+            }),
+        ),
+    );
+    let mut session = agent.new_session();
+
+    agent.run(&mut session, "hi").await.unwrap();
+    agent.run(&mut session, "again").await.unwrap();
+
+    assert_eq!(loads.load(Ordering::SeqCst), 1);
+    assert_eq!(local.received().len(), 2);
+    assert_eq!(session.answered_by[1].backend, "local");
+}
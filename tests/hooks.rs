@@ -0,0 +1,91 @@
+//! Pre- and post-tool hooks around the tool calls of a run.
+
+mod common;
+
+use common::StandIn;
+use junior::hooks::{Hook, Hooks};
+use junior::llm::Backend;
+use junior::{Agent, ToolRegistry};
+use std::fs;
+
+const WRITE_CALL: &str = r#"{"choices":[{"message":{"role":"assistant","content":null,"tool_calls":[{"id":"call_1","type":"function","function":{"name":"write_file","arguments":"{\"path\":\"src/gen.rs\",\"contents\":\"fn main() {}\"}"}}]}}]}"#;
+
+fn hook(command: &str) -> Hook {
+    Hook {
+        command: command.to_string(),
+        tools: vec!["write_file".to_string()],
+        paths: vec!["src/**".to_string()],
+        append_output: false,
+    }
+}
+
+/// Runs a prompt whose first reply writes `src/gen.rs`, and returns the
+/// tool result sent back to the model.
+async fn write_with(hooks: Hooks, workspace: &std::path::Path) -> String {
+    let server = StandIn::scripted(vec![("200 OK", "", WRITE_CALL)]);
+    fs::create_dir(workspace.join("src")).unwrap();
+    let mut agent = Agent::new("gpt-4", "", "");
+    agent.backend = Backend::new(server.endpoint(), "secret");
+    agent.set_workspace(workspace.to_path_buf()).unwrap();
+    agent.tools = ToolRegistry::builtin();
+    agent.hooks = hooks;
+    agent.budget = None;
+    agent.compaction = None;
+    let mut session = agent.new_session();
+
+    let reply = agent.run(&mut session, "generate main").await.unwrap();
+
+    assert_eq!(reply.text_content().unwrap(), "ok");
+    let received = server.received();
+    assert_eq!(received.len(), 2);
+    let body: serde_json::Value = serde_json::from_str(&received[1].body).unwrap();
+    let result = body["messages"].as_array().unwrap().last().unwrap().clone();
+    assert_eq!(result["role"], "tool");
+    assert_eq!(result["tool_call_id"], "call_1");
+    result["content"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn a_vetoing_pre_hook_tells_the_model_why() {
+    let workspace = tempfile::tempdir().unwrap();
+    let command = "echo 'generated code is read-only' >&2; exit 1";
+    let hooks = Hooks {
+        pre_tool: vec![hook(command)],
+        ..Hooks::default()
+    };
+
+    let result = write_with(hooks, workspace.path()).await;
+
+    assert_eq!(
+        result,
+        format!(
+            "Error: Blocked by hook `{}`: generated code is read-only",
+            command
+        )
+    );
+    assert!(!workspace.path().join("src/gen.rs").exists());
+}
+
+#[tokio::test]
+async fn a_failing_post_hook_is_reported_with_the_tool_output() {
+    let workspace = tempfile::tempdir().unwrap();
+    let hooks = Hooks {
+        post_tool: vec![hook("echo 'rustfmt: parse error'; exit 2")],
+        ..Hooks::default()
+    };
+
+    let result = write_with(hooks, workspace.path()).await;
+
+    assert!(!result.starts_with("Error:"), "{}", result);
+    assert!(
+        result.ends_with(
+            "\n\nPost-tool hook `echo 'rustfmt: parse error'; exit 2` failed:\nrustfmt: parse error"
+        ),
+        "{}",
+        result
+    );
+    assert_eq!(
+        fs::read_to_string(workspace.path().join("src/gen.rs")).unwrap(),
+        "fn main() {}"
+    );
+}
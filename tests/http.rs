@@ -0,0 +1,378 @@
+//! Transport settings checked against a stand-in chat completions server
+//! on a local port.
+
+mod common;
+
+use std::collections::BTreeMap;
+use std::net::TcpListener;
+use std::path::Path;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use common::{StandIn, quick_retries};
+use junior::ChatMessage;
+use junior::config::{ConfigLayer, ConfigSource, config_from_layers};
+use junior::error::Error;
+use junior::http::{AuthScheme, HttpConfig, RetryConfig};
+use junior::llm::{Backend, GenerationParams, send_to_llm};
+
+async fn send(backend: &Backend) -> anyhow::Result<ChatMessage> {
+    send_to_llm(
+        backend,
+        &[ChatMessage::user("hi")],
+        "gpt-4",
+        &[],
+        &GenerationParams::default(),
+        &|_| {},
+    )
+    .await
+}
+
+/// Sends a request and collects the retry notices.
+async fn send_noting_retries(backend: &Backend) -> (anyhow::Result<ChatMessage>, Vec<String>) {
+    let notices = Mutex::new(Vec::new());
+    let result = send_to_llm(
+        backend,
+        &[ChatMessage::user("hi")],
+        "gpt-4",
+        &[],
+        &GenerationParams::default(),
+        &|notice| notices.lock().unwrap().push(notice.to_string()),
+    )
+    .await;
+    (result, notices.into_inner().unwrap())
+}
+
+#[tokio::test]
+async fn sends_bearer_auth_and_configured_headers() {
+    let server = StandIn::start();
+    let http = HttpConfig {
+        headers: BTreeMap::from([
+            ("X-Gateway-Team".to_string(), "infra".to_string()),
+            ("X-Package".to_string(), "${CARGO_PKG_NAME}".to_string()),
+        ]),
+        ..HttpConfig::default()
+    };
+    let backend = Backend::with_http(server.endpoint(), "secret", &http).unwrap();
+
+    let reply = send(&backend).await.unwrap();
+
+    assert_eq!(reply.text_content().unwrap(), "ok");
+    let request = &server.received()[0];
+    assert_eq!(request.request_line, "POST /v1/chat/completions HTTP/1.1");
+    assert_eq!(request.headers["authorization"], "Bearer secret");
+    assert_eq!(request.headers["x-gateway-team"], "infra");
+    assert_eq!(request.headers["x-package"], "junior");
+}
+
+#[tokio::test]
+async fn azure_style_auth_sends_api_key_header_and_version() {
+    let server = StandIn::start();
+    let http = HttpConfig {
+        auth: AuthScheme::ApiKey,
+        api_version: Some("2024-06-01".to_string()),
+        ..HttpConfig::default()
+    };
+    let backend = Backend::with_http(server.endpoint(), "secret", &http).unwrap();
+
+    send(&backend).await.unwrap();
+
+    let request = &server.received()[0];
+    assert_eq!(
+        request.request_line,
+        "POST /v1/chat/completions?api-version=2024-06-01 HTTP/1.1"
+    );
+    assert_eq!(request.headers["api-key"], "secret");
+    assert!(!request.headers.contains_key("authorization"));
+}
+
+#[tokio::test]
+async fn no_auth_sends_no_key() {
+    let server = StandIn::start();
+    let http = HttpConfig {
+        auth: AuthScheme::None,
+        ..HttpConfig::default()
+    };
+    let backend = Backend::with_http(server.endpoint(), "secret", &http).unwrap();
+
+    send(&backend).await.unwrap();
+
+    let request = &server.received()[0];
+    assert!(!request.headers.contains_key("authorization"));
+    assert!(!request.headers.contains_key("api-key"));
+}
+
+#[tokio::test]
+async fn requests_go_through_the_proxy() {
+    let proxy = StandIn::start();
+    let http = HttpConfig {
+        proxy: Some(proxy.url.clone()),
+        ..HttpConfig::default()
+    };
+    let backend =
+        Backend::with_http("http://llm.invalid/v1/chat/completions", "secret", &http).unwrap();
+
+    send(&backend).await.unwrap();
+
+    let request = &proxy.received()[0];
+    assert_eq!(
+        request.request_line,
+        "POST http://llm.invalid/v1/chat/completions HTTP/1.1"
+    );
+    assert_eq!(request.headers["host"], "llm.invalid");
+}
+
+#[tokio::test]
+async fn one_client_reuses_its_connection() {
+    let server = StandIn::start();
+    let backend = Backend::with_http(server.endpoint(), "secret", &HttpConfig::default()).unwrap();
+
+    send(&backend).await.unwrap();
+    send(&backend).await.unwrap();
+
+    let received = server.received();
+    assert_eq!(received.len(), 2);
+    assert_eq!(received[0].connection, received[1].connection);
+}
+
+#[tokio::test]
+async fn times_out_when_the_server_does_not_answer() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = format!(
+        "http://{}/v1/chat/completions",
+        listener.local_addr().unwrap()
+    );
+    // Accepts the connection but never replies.
+    let _silent = thread::spawn(move || listener.accept().map(|(stream, _)| stream));
+    let http = HttpConfig {
+        timeout_secs: 1,
+        ..quick_retries(0)
+    };
+    let backend = Backend::with_http(endpoint.clone(), "secret", &http).unwrap();
+
+    let error = send(&backend).await.unwrap_err();
+
+    let Some(Error::Unavailable { message }) = Error::find(&error) else {
+        panic!("{:#}", error);
+    };
+    assert_eq!(message, &format!("the request to {} timed out", endpoint));
+}
+
+#[tokio::test]
+async fn retries_server_errors_until_one_succeeds() {
+    let server = StandIn::scripted(vec![
+        ("503 Service Unavailable", "", "overloaded"),
+        ("500 Internal Server Error", "", "oops"),
+    ]);
+    let backend = Backend::with_http(server.endpoint(), "secret", &quick_retries(3)).unwrap();
+
+    let (reply, notices) = send_noting_retries(&backend).await;
+
+    assert_eq!(reply.unwrap().text_content().unwrap(), "ok");
+    assert_eq!(server.received().len(), 3);
+    assert_eq!(notices.len(), 2);
+    assert!(
+        notices[0].starts_with(
+            "The provider is unavailable: 503 Service Unavailable - overloaded; retrying in "
+        ),
+        "{}",
+        notices[0]
+    );
+    assert!(notices[1].ends_with("(2 of 3)"), "{}", notices[1]);
+}
+
+#[tokio::test]
+async fn gives_up_after_the_last_retry() {
+    let server = StandIn::scripted(vec![("429 Too Many Requests", "", "slow down"); 3]);
+    let backend = Backend::with_http(server.endpoint(), "secret", &quick_retries(2)).unwrap();
+
+    let (reply, notices) = send_noting_retries(&backend).await;
+
+    let error = reply.unwrap_err();
+    assert!(matches!(
+        Error::find(&error),
+        Some(Error::RateLimited { .. })
+    ));
+    assert_eq!(error.to_string(), "Rate limited by the provider: slow down");
+    assert_eq!(server.received().len(), 3);
+    assert_eq!(notices.len(), 2);
+}
+
+#[tokio::test]
+async fn does_not_retry_client_errors() {
+    let server = StandIn::scripted(vec![("400 Bad Request", "", "bad model")]);
+    let backend = Backend::with_http(server.endpoint(), "secret", &quick_retries(3)).unwrap();
+
+    let (reply, notices) = send_noting_retries(&backend).await;
+
+    let error = reply.unwrap_err();
+    assert!(matches!(Error::find(&error), Some(Error::Provider { .. })));
+    assert_eq!(server.received().len(), 1);
+    assert!(notices.is_empty());
+}
+
+#[tokio::test]
+async fn waits_as_long_as_retry_after_asks() {
+    let server = StandIn::scripted(vec![(
+        "429 Too Many Requests",
+        "Retry-After: 1\r\n",
+        "slow down",
+    )]);
+    let backend = Backend::with_http(server.endpoint(), "secret", &quick_retries(1)).unwrap();
+
+    let (reply, notices) = send_noting_retries(&backend).await;
+
+    assert!(reply.is_ok());
+    assert!(notices[0].contains("retrying in 1.0s"), "{}", notices[0]);
+}
+
+#[tokio::test]
+async fn fails_when_retry_after_is_too_long() {
+    let server = StandIn::scripted(vec![(
+        "503 Service Unavailable",
+        "Retry-After: 3600\r\n",
+        "maintenance",
+    )]);
+    let backend = Backend::with_http(server.endpoint(), "secret", &quick_retries(3)).unwrap();
+
+    let (reply, notices) = send_noting_retries(&backend).await;
+
+    assert!(reply.is_err());
+    assert_eq!(server.received().len(), 1);
+    assert!(notices.is_empty());
+}
+
+#[tokio::test]
+async fn retries_when_the_connection_is_refused() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = format!(
+        "http://{}/v1/chat/completions",
+        listener.local_addr().unwrap()
+    );
+    drop(listener);
+    let backend = Backend::with_http(endpoint.clone(), "secret", &quick_retries(2)).unwrap();
+
+    let (reply, notices) = send_noting_retries(&backend).await;
+
+    let error = reply.unwrap_err();
+    let Some(Error::Unavailable { message }) = Error::find(&error) else {
+        panic!("{:#}", error);
+    };
+    assert_eq!(
+        message,
+        &format!("could not connect to {}: Connection refused", endpoint)
+    );
+    assert_eq!(notices.len(), 2);
+}
+
+#[test]
+fn backoff_grows_with_jitter_up_to_the_limit() {
+    let retry = RetryConfig {
+        max_retries: 10,
+        initial_backoff_ms: 1_000,
+        max_backoff_ms: 5_000,
+        ..RetryConfig::default()
+    };
+    for (attempt, full) in [(0, 1_000), (1, 2_000), (2, 4_000), (3, 5_000), (9, 5_000)] {
+        let delay = retry.delay(attempt, None).unwrap();
+        assert!(
+            delay >= Duration::from_millis(full / 2) && delay <= Duration::from_millis(full),
+            "retry {} waited {:?}",
+            attempt,
+            delay
+        );
+    }
+    assert_eq!(retry.delay(10, None), None);
+    assert_eq!(
+        retry.delay(0, Some(Duration::from_secs(7))),
+        Some(Duration::from_secs(7))
+    );
+}
+
+#[test]
+fn loads_a_ca_bundle() {
+    let http = HttpConfig {
+        ca_bundle: Some(fixture("ca.pem")),
+        ..HttpConfig::default()
+    };
+    http.build_client().unwrap();
+}
+
+#[test]
+fn rejects_a_missing_or_empty_ca_bundle() {
+    let missing = HttpConfig {
+        ca_bundle: Some(fixture("missing.pem")),
+        ..HttpConfig::default()
+    };
+    let error = missing.build_client().unwrap_err().to_string();
+    assert!(error.starts_with("Failed to read CA bundle"), "{}", error);
+
+    let empty = HttpConfig {
+        ca_bundle: Some(fixture("../http.rs")),
+        ..HttpConfig::default()
+    };
+    let error = empty.build_client().unwrap_err().to_string();
+    assert!(error.contains("contains no PEM certificates"), "{}", error);
+}
+
+#[test]
+fn rejects_invalid_headers_and_proxies() {
+    let header = HttpConfig {
+        headers: BTreeMap::from([("Bad Header".to_string(), "x".to_string())]),
+        ..HttpConfig::default()
+    };
+    assert!(header.build_client().is_err());
+
+    let proxy = HttpConfig {
+        proxy: Some("not a url".to_string()),
+        ..HttpConfig::default()
+    };
+    assert!(proxy.build_client().is_err());
+}
+
+#[test]
+fn profile_http_settings_merge_over_the_top_level() {
+    let file: toml::Table = toml::from_str(
+        r#"
+        [http]
+        proxy = "http://proxy.corp:3128"
+        headers = { "X-Team" = "infra" }
+        "#,
+    )
+    .unwrap();
+    let profile: toml::Table = toml::from_str(
+        r#"
+        [http]
+        auth = "api-key"
+        api_version = "2024-06-01"
+        headers = { "X-Deployment" = "eu" }
+        "#,
+    )
+    .unwrap();
+    let layers = [
+        ConfigLayer {
+            source: ConfigSource::File("config.toml".into()),
+            values: file,
+        },
+        ConfigLayer {
+            source: ConfigSource::Profile("azure".to_string()),
+            values: profile,
+        },
+    ];
+
+    let config = config_from_layers(&layers).unwrap();
+
+    assert_eq!(config.http.auth, AuthScheme::ApiKey);
+    assert_eq!(config.http.api_version.as_deref(), Some("2024-06-01"));
+    assert_eq!(config.http.proxy.as_deref(), Some("http://proxy.corp:3128"));
+    assert_eq!(config.http.headers.len(), 2);
+}
+
+fn fixture(name: &str) -> String {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+        .to_string_lossy()
+        .to_string()
+}
@@ -0,0 +1,27 @@
+//! Project memory in an embedded agent's workspace.
+
+use junior::Agent;
+use serde_json::json;
+
+#[tokio::test]
+async fn remembers_in_the_agent_workspace_and_reads_it_back() {
+    let workspace = tempfile::tempdir().unwrap();
+    let mut agent = Agent::new("gpt-4", "", "");
+    agent.set_workspace(workspace.path().to_path_buf()).unwrap();
+
+    agent
+        .tools
+        .call("remember", json!({ "fact": "Use tabs for indentation." }))
+        .await
+        .unwrap();
+
+    let memory = std::fs::read_to_string(workspace.path().join("JUNIOR.md")).unwrap();
+    assert!(memory.contains("Use tabs for indentation."));
+    let session = agent.new_session();
+    assert!(
+        session.messages[0]
+            .text_content()
+            .unwrap()
+            .contains("Use tabs for indentation.")
+    );
+}
@@ -0,0 +1,57 @@
+//! A forced tool choice across the turns of a run.
+
+mod common;
+
+use common::StandIn;
+use junior::llm::Backend;
+use junior::{Agent, ToolRegistry};
+
+const TOOL_CALL: &str = r#"{"choices":[{"message":{"role":"assistant","content":null,"tool_calls":[{"id":"call_1","type":"function","function":{"name":"read_file","arguments":"{\"path\":\"missing.txt\"}"}}]}}]}"#;
+
+fn tool_choice_of(body: &str) -> serde_json::Value {
+    let body: serde_json::Value = serde_json::from_str(body).unwrap();
+    body["tool_choice"].clone()
+}
+
+async fn run_with(tool_choice: &str) -> Vec<serde_json::Value> {
+    let server = StandIn::scripted(vec![("200 OK", "", TOOL_CALL)]);
+    let workspace = tempfile::tempdir().unwrap();
+    let mut agent = Agent::new("gpt-4", "", "");
+    agent.backend = Backend::new(server.endpoint(), "secret");
+    agent.set_workspace(workspace.path().to_path_buf()).unwrap();
+    agent.tools = ToolRegistry::builtin();
+    agent.generation.tool_choice = Some(tool_choice.to_string());
+    agent.budget = None;
+    agent.compaction = None;
+    let mut session = agent.new_session();
+
+    let reply = agent.run(&mut session, "hi").await.unwrap();
+
+    assert_eq!(reply.text_content().unwrap(), "ok");
+    server
+        .received()
+        .iter()
+        .map(|request| tool_choice_of(&request.body))
+        .collect()
+}
+
+#[tokio::test]
+async fn a_forced_tool_applies_to_the_first_request_only() {
+    let choices = run_with("read_file").await;
+
+    assert_eq!(
+        choices,
+        [
+            serde_json::json!({ "type": "function", "function": { "name": "read_file" } }),
+            serde_json::json!("auto"),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn required_applies_to_the_first_request_only() {
+    assert_eq!(
+        run_with("required").await,
+        [serde_json::json!("required"), serde_json::json!("auto")]
+    );
+}
@@ -0,0 +1,49 @@
+//! Calls to tools left out of the run's tool set.
+
+mod common;
+
+use common::StandIn;
+use junior::Agent;
+use junior::config::Config;
+use junior::llm::Backend;
+
+const WRITE_CALL: &str = r#"{"choices":[{"message":{"role":"assistant","content":null,"tool_calls":[{"id":"call_1","type":"function","function":{"name":"write_file","arguments":"{\"path\":\"notes.txt\",\"contents\":\"hi\"}"}}]}}]}"#;
+
+#[tokio::test]
+async fn a_call_to_a_tool_outside_the_set_is_refused() {
+    let server = StandIn::scripted(vec![("200 OK", "", WRITE_CALL)]);
+    let workspace = tempfile::tempdir().unwrap();
+    let mut agent = Agent::new("gpt-4", "", "");
+    agent.backend = Backend::new(server.endpoint(), "secret");
+    agent.set_workspace(workspace.path().to_path_buf()).unwrap();
+    agent.tools = Config::default()
+        .tool_registry(Some("full"), &["write_file".to_string()])
+        .unwrap();
+    agent.budget = None;
+    agent.compaction = None;
+    let mut session = agent.new_session();
+
+    let reply = agent.run(&mut session, "take notes").await.unwrap();
+
+    assert_eq!(reply.text_content().unwrap(), "ok");
+    assert!(!workspace.path().join("notes.txt").exists());
+    let received = server.received();
+    for request in &received {
+        let body: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+        let offered: Vec<&str> = body["tools"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|tool| tool["function"]["name"].as_str().unwrap())
+            .collect();
+        assert!(offered.contains(&"read_file"), "{:?}", offered);
+        assert!(!offered.contains(&"write_file"), "{:?}", offered);
+    }
+    let body: serde_json::Value = serde_json::from_str(&received[1].body).unwrap();
+    let result = body["messages"].as_array().unwrap().last().unwrap().clone();
+    assert_eq!(result["tool_call_id"], "call_1");
+    assert_eq!(
+        result["content"],
+        "Error: Unknown tool function: write_file"
+    );
+}